@@ -8,12 +8,18 @@ mod utils;
 use core::time::Duration;
 use egui::TextureHandle;
 use embedded_graphics::pixelcolor::Gray8;
-use embedded_graphics::prelude::{Dimensions, GrayColor};
+use embedded_graphics::prelude::{Dimensions, GrayColor, Size};
 use embedded_graphics::text::Text;
 use embedded_hal::digital::OutputPin;
 use embedded_hal::digital::PinState;
 use embedded_hal_sim::gpio::{self, Input, Output};
-use embedded_hal_sim::{graphics, sleep};
+use embedded_hal_sim::graphics::{self, FrameSubscriber};
+use embedded_hal_sim::i2c::I2cBus;
+use embedded_hal_sim::sleep;
+use embedded_hal_sim::touch::{
+    TouchStimulus,
+    ft6x06::{self, Ft6x06},
+};
 use futures::select;
 use parking_lot::Mutex;
 use std::sync::Arc;
@@ -35,11 +41,25 @@ fn main() {
 
     let (reset, reset_stimulus) = gpio::new(PinState::Low);
     let (start_stop, start_stop_stimulus) = gpio::new(PinState::Low);
-    let (display, frame_buffer) = graphics::Display::new();
+    let (mut display, frame_buffer) = graphics::Display::new();
+    let frames = display.subscribe();
+    let (touch_irq, touch_irq_stimulus) = gpio::new(PinState::High);
+    let (touch_controller, touch) = Ft6x06::new(SIZE, touch_irq_stimulus);
+    let mut i2c = I2cBus::new();
+    i2c.attach(ft6x06::ADDRESS, touch_controller);
 
     run_wasm(
-        |cc| MyApp::new(cc, frame_buffer, reset_stimulus, start_stop_stimulus),
-        || async { simulated_app(display, reset, start_stop).await },
+        |cc| {
+            MyApp::new(
+                cc,
+                frame_buffer,
+                frames,
+                reset_stimulus,
+                start_stop_stimulus,
+                touch,
+            )
+        },
+        || async { simulated_app(display, reset, start_stop, i2c, touch_irq).await },
     );
 }
 
@@ -50,15 +70,34 @@ async fn main() {
 
     let (reset, reset_stimulus) = gpio::new(PinState::Low);
     let (start_stop, start_stop_stimulus) = gpio::new(PinState::Low);
-    let (display, frame_buffer) = graphics::Display::new();
+    let (mut display, frame_buffer) = graphics::Display::new();
+    let frames = display.subscribe();
+    let (touch_irq, touch_irq_stimulus) = gpio::new(PinState::High);
+    let (touch_controller, touch) = Ft6x06::new(SIZE, touch_irq_stimulus);
+    let mut i2c = I2cBus::new();
+    i2c.attach(ft6x06::ADDRESS, touch_controller);
 
-    thread::spawn(|| ui(frame_buffer, reset_stimulus, start_stop_stimulus));
+    thread::spawn(|| {
+        ui(
+            frame_buffer,
+            frames,
+            reset_stimulus,
+            start_stop_stimulus,
+            touch,
+        )
+    });
 
-    simulated_app(display, reset, start_stop).await;
+    simulated_app(display, reset, start_stop, i2c, touch_irq).await;
 }
 
 #[cfg(not(target_arch = "wasm32"))]
-fn ui(frame_buffer: Arc<Mutex<[[u8; COLS]; ROWS]>>, reset: Output, start_stop: Output) {
+fn ui(
+    frame_buffer: Arc<Mutex<[[Gray8; COLS]; ROWS]>>,
+    frames: FrameSubscriber,
+    reset: Output,
+    start_stop: Output,
+    touch: TouchStimulus,
+) {
     let event_loop_builder: Option<EventLoopBuilderHook> = Some(Box::new(|event_loop_builder| {
         event_loop_builder.with_any_thread(true);
     }));
@@ -69,7 +108,16 @@ fn ui(frame_buffer: Arc<Mutex<[[u8; COLS]; ROWS]>>, reset: Output, start_stop: O
     eframe::run_native(
         "My hardware simulator",
         options,
-        Box::new(|cc| Ok(Box::new(MyApp::new(cc, frame_buffer, reset, start_stop)))),
+        Box::new(|cc| {
+            Ok(Box::new(MyApp::new(
+                cc,
+                frame_buffer,
+                frames,
+                reset,
+                start_stop,
+                touch,
+            )))
+        }),
     )
     .unwrap();
 }
@@ -78,12 +126,15 @@ async fn simulated_app(
     mut display: graphics::Display<COLS, ROWS>,
     mut reset: Input,
     mut start_stop: Input,
+    mut i2c: I2cBus,
+    mut touch_irq: Input,
 ) -> ! {
     use embedded_graphics::Drawable;
     use embedded_graphics::draw_target::DrawTarget;
     use embedded_graphics::mono_font::{MonoTextStyle, ascii::FONT_6X10};
     use embedded_graphics::prelude::Point;
     use embedded_graphics::text::Alignment;
+    use embedded_hal::i2c::I2c;
     use embedded_hal_async::digital::Wait;
     use futures::FutureExt;
 
@@ -105,9 +156,18 @@ async fn simulated_app(
         )
         .draw(&mut display)
         .unwrap();
+        display.flush();
 
         select! {
             _ = start_stop.wait_for_rising_edge().fuse() => is_started = !is_started,
+            // Tapping the display starts and stops as well
+            _ = touch_irq.wait_for_falling_edge().fuse() => {
+                let mut touches = [0];
+                i2c.write_read(ft6x06::ADDRESS, &[0x02], &mut touches).unwrap();
+                if touches[0] > 0 {
+                    is_started = !is_started;
+                }
+            },
             _ = reset.wait_for_rising_edge().fuse() => {
                counter = 0;
                is_started = false;
@@ -121,28 +181,35 @@ async fn simulated_app(
 
 const COLS: usize = 128;
 const ROWS: usize = 32;
+const SIZE: Size = Size::new(COLS as u32, ROWS as u32);
 
 struct MyApp {
-    frame_buffer: Arc<Mutex<[[u8; COLS]; ROWS]>>,
+    frame_buffer: Arc<Mutex<[[Gray8; COLS]; ROWS]>>,
+    frames: FrameSubscriber,
     reset: Output,
     start_stop: Output,
+    touch: TouchStimulus,
     texture: TextureHandle,
 }
 
 impl MyApp {
     pub fn new(
         cc: &eframe::CreationContext<'_>,
-        frame_buffer: Arc<Mutex<[[u8; COLS]; ROWS]>>,
+        frame_buffer: Arc<Mutex<[[Gray8; COLS]; ROWS]>>,
+        frames: FrameSubscriber,
         reset: Output,
         start_stop: Output,
+        touch: TouchStimulus,
     ) -> Self {
         Self {
             frame_buffer,
+            frames,
             reset,
             start_stop,
+            touch,
             texture: cc.egui_ctx.load_texture(
-                "noise",
-                egui::ColorImage::example(),
+                "display",
+                egui::ColorImage::filled([COLS, ROWS], egui::Color32::BLACK),
                 egui::TextureOptions::NEAREST,
             ),
         }
@@ -154,15 +221,27 @@ impl eframe::App for MyApp {
         ctx.request_repaint_after(Duration::from_millis(20));
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.heading("My display-counter Application");
+            // Only upload what changed in the frames flushed by the firmware, as the
+            // panel shows it
+            if let Some(dirty) = self.frames.take_dirty() {
+                let panel = self.frames.panel();
+                let (x, y) = (dirty.top_left.x as usize, dirty.top_left.y as usize);
+                let (width, height) = (dirty.size.width as usize, dirty.size.height as usize);
+                let rgba: Vec<u8> = self.frame_buffer.lock()[y..y + height]
+                    .iter()
+                    .flat_map(|row| panel.to_rgba(&row[x..x + width]))
+                    .collect();
+                let image = egui::ColorImage::from_rgba_unmultiplied([width, height], &rgba);
+                self.texture
+                    .set_partial([x, y], image, egui::TextureOptions::NEAREST);
+            }
             {
-                let image = egui::ColorImage::from_gray(
-                    [COLS, ROWS],
-                    self.frame_buffer.lock().as_flattened(),
-                );
-                self.texture.set(image, egui::TextureOptions::NEAREST);
                 let size = self.texture.size_vec2();
                 let sized_texture = egui::load::SizedTexture::new(&self.texture, size);
-                ui.add(egui::Image::new(sized_texture).fit_to_exact_size(size));
+                let image = egui::Image::new(sized_texture)
+                    .fit_to_exact_size(size)
+                    .sense(egui::Sense::click_and_drag());
+                self.touch.follow_pointer(&ui.add(image));
             }
 
             self.reset
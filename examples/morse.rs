@@ -85,7 +85,7 @@ fn ui(uart: UartStimulus, led: Input) {
 async fn simulated_app(mut uart: Uart, mut led: Output) -> ! {
     let mut buf = [0; 256];
     loop {
-        let count = uart.read_until_idle(&mut buf).await;
+        let count = uart.read_until_idle(&mut buf).await.unwrap();
         let data = &buf[..count];
         for byte in data {
             blink_morse(byte.to_ascii_uppercase() as char, &mut led).await;
@@ -0,0 +1,96 @@
+use std::time::Duration;
+
+use crate::time;
+
+/// Delay provider using the simulator clock, see [crate::time]
+///
+/// The blocking delay sleeps the current thread in real time. When a
+/// [time::VirtualClock] is in use it instead moves the virtual time forward, just
+/// like a busy wait on real hardware lets time pass without anything else running.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Delay;
+
+impl Delay {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+/// Block the current thread for `duration` on the clock used by this thread
+pub(crate) fn block_for(duration: Duration) {
+    if let Some(clock) = time::current() {
+        clock.advance(duration);
+        return;
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    std::thread::sleep(duration);
+
+    // Sleeping the thread is not supported in the browser
+    #[cfg(target_arch = "wasm32")]
+    {
+        let deadline = time::now() + duration;
+        while time::now() < deadline {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+impl embedded_hal::delay::DelayNs for Delay {
+    fn delay_ns(&mut self, ns: u32) {
+        block_for(Duration::from_nanos(u64::from(ns)));
+    }
+
+    fn delay_us(&mut self, us: u32) {
+        block_for(Duration::from_micros(u64::from(us)));
+    }
+
+    fn delay_ms(&mut self, ms: u32) {
+        block_for(Duration::from_millis(u64::from(ms)));
+    }
+}
+
+impl embedded_hal_async::delay::DelayNs for Delay {
+    async fn delay_ns(&mut self, ns: u32) {
+        time::sleep(Duration::from_nanos(u64::from(ns))).await
+    }
+
+    async fn delay_us(&mut self, us: u32) {
+        time::sleep(Duration::from_micros(u64::from(us))).await
+    }
+
+    async fn delay_ms(&mut self, ms: u32) {
+        time::sleep(Duration::from_millis(u64::from(ms))).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Delay;
+    use crate::time::VirtualClock;
+    use futures::FutureExt;
+    use std::{pin::pin, time::Duration};
+
+    #[test]
+    fn blocking() {
+        let clock = VirtualClock::manual();
+        let _guard = clock.enter();
+        embedded_hal::delay::DelayNs::delay_ms(&mut Delay::new(), 25);
+        assert_eq!(clock.now().since_start(), Duration::from_millis(25));
+    }
+
+    #[test]
+    fn async_delay() {
+        let clock = VirtualClock::manual();
+        let _guard = clock.enter();
+        let mut delay = Delay::new();
+        let mut future = pin!(embedded_hal_async::delay::DelayNs::delay_us(
+            &mut delay, 500
+        ));
+        assert!(future.as_mut().now_or_never().is_none());
+        clock.advance(Duration::from_micros(499));
+        assert!(future.as_mut().now_or_never().is_none());
+        clock.advance(Duration::from_micros(1));
+        assert!(future.now_or_never().is_some());
+    }
+}
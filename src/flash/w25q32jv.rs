@@ -1,21 +1,289 @@
-use std::sync::{Arc, RwLock};
+use std::{
+    ops::Range,
+    sync::{Arc, RwLock},
+};
 
 use embedded_storage::nor_flash::{NorFlash as SyncNorFlash, ReadNorFlash as SyncReadNorFlash};
 use embedded_storage_async::nor_flash::{
     NorFlash as AsyncNorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash as AsyncReadNorFlash,
 };
 
+use crate::spi::{IDLE_BYTE, SpiDeviceModel};
+
 pub const PAGE_SIZE: u32 = 256;
 pub const SECTOR_SIZE: u32 = PAGE_SIZE * 16;
+pub const BLOCK_32K_SIZE: u32 = SECTOR_SIZE * 8;
+pub const BLOCK_64K_SIZE: u32 = SECTOR_SIZE * 16;
+
+/// Manufacturer id followed by the two byte device id as returned by the JEDEC id command
+pub const JEDEC_ID: [u8; 3] = [0xEF, 0x40, 0x16];
+
+const UNIQUE_ID: [u8; 8] = [0xD2, 0x61, 0x0C, 0x3B, 0x17, 0x42, 0x5A, 0x90];
+
+mod command {
+    pub const WRITE_ENABLE: u8 = 0x06;
+    pub const WRITE_DISABLE: u8 = 0x04;
+    pub const READ_STATUS_1: u8 = 0x05;
+    pub const READ_STATUS_2: u8 = 0x35;
+    pub const READ_STATUS_3: u8 = 0x15;
+    pub const READ_DATA: u8 = 0x03;
+    pub const FAST_READ: u8 = 0x0B;
+    pub const PAGE_PROGRAM: u8 = 0x02;
+    pub const SECTOR_ERASE: u8 = 0x20;
+    pub const BLOCK_ERASE_32K: u8 = 0x52;
+    pub const BLOCK_ERASE_64K: u8 = 0xD8;
+    pub const CHIP_ERASE: u8 = 0xC7;
+    pub const CHIP_ERASE_ALT: u8 = 0x60;
+    pub const POWER_DOWN: u8 = 0xB9;
+    pub const RELEASE_POWER_DOWN: u8 = 0xAB;
+    pub const MANUFACTURER_DEVICE_ID: u8 = 0x90;
+    pub const JEDEC_ID: u8 = 0x9F;
+    pub const UNIQUE_ID: u8 = 0x4B;
+    pub const ENABLE_RESET: u8 = 0x66;
+    pub const RESET: u8 = 0x99;
+}
+
+/// Write enable latch bit in status register 1
+pub const STATUS_WEL: u8 = 1 << 1;
 
 pub struct W25q32jv {
     data: Arc<RwLock<Box<[u8]>>>,
 }
 
 impl W25q32jv {
+    /// Flash backed by `data`, which must not be empty
     pub fn new(data: Arc<RwLock<Box<[u8]>>>) -> Self {
+        assert!(!data.read().unwrap().is_empty(), "empty flash store");
         Self { data }
     }
+
+    /// Erase the 32K block starting at `from`
+    pub fn erase_block_32k(&mut self, from: u32) -> Result<(), Error> {
+        let to = from.checked_add(BLOCK_32K_SIZE).ok_or(Error::OutOfBounds)?;
+        self.erase_aligned(from, to, BLOCK_32K_SIZE)
+    }
+
+    /// Erase the 64K block starting at `from`
+    pub fn erase_block_64k(&mut self, from: u32) -> Result<(), Error> {
+        let to = from.checked_add(BLOCK_64K_SIZE).ok_or(Error::OutOfBounds)?;
+        self.erase_aligned(from, to, BLOCK_64K_SIZE)
+    }
+
+    pub fn erase_chip(&mut self) -> Result<(), Error> {
+        self.data.write().unwrap().fill(0xFF);
+        Ok(())
+    }
+
+    /// Erase `from..to`, both have to be multiples of `size`
+    fn erase_aligned(&mut self, from: u32, to: u32, size: u32) -> Result<(), Error> {
+        if !from.is_multiple_of(size) || !to.is_multiple_of(size) {
+            return Err(Error::NotAligned);
+        }
+        if from > to {
+            return Err(Error::OutOfBounds);
+        }
+        let mut data = self.data.write().unwrap();
+        let range = checked_range(&data, from, (to - from) as usize)?;
+        data[range].fill(0xFF);
+        Ok(())
+    }
+}
+
+/// The part of `data` starting at `offset` with `len` bytes, if it is all there
+fn checked_range(data: &[u8], offset: u32, len: usize) -> Result<Range<usize>, Error> {
+    let start = offset as usize;
+    match start.checked_add(len) {
+        Some(end) if end <= data.len() => Ok(start..end),
+        _ => Err(Error::OutOfBounds),
+    }
+}
+
+/// The W25Q32JV as seen over spi
+///
+/// This speaks the command set of the real chip and shares its backing store with
+/// [W25q32jv], so data written through one is visible through the other. Attach it
+/// to a [crate::spi::SpiBus] or wrap it in a [crate::spi::SpiDevice].
+///
+/// Program and erase commands take effect when chip select is released and
+/// complete instantly, so the busy bit is never set.
+pub struct W25q32jvSpi {
+    data: Arc<RwLock<Box<[u8]>>>,
+    write_enabled: bool,
+    powered_down: bool,
+    reset_enabled: bool,
+    command: Option<u8>,
+    /// Bytes received after the command byte in the current transaction
+    count: usize,
+    address: u32,
+    page_buffer: Option<[u8; PAGE_SIZE as usize]>,
+}
+
+impl W25q32jvSpi {
+    /// Flash backed by `data`, which must not be empty
+    pub fn new(data: Arc<RwLock<Box<[u8]>>>) -> Self {
+        assert!(!data.read().unwrap().is_empty(), "empty flash store");
+        Self {
+            data,
+            write_enabled: false,
+            powered_down: false,
+            reset_enabled: false,
+            command: None,
+            count: 0,
+            address: 0,
+            page_buffer: None,
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        self.data.read().unwrap().len()
+    }
+
+    fn status_1(&self) -> u8 {
+        if self.write_enabled { STATUS_WEL } else { 0 }
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let data = self.data.read().unwrap();
+        let byte = data[self.address as usize % data.len()];
+        self.address = self.address.wrapping_add(1);
+        byte
+    }
+
+    /// Handle a byte following the command byte, `index` is its position after the command
+    fn command_byte(&mut self, command: u8, index: usize, mosi: u8) -> u8 {
+        // Commands that start with a 24 bit address
+        let address_bytes = match command {
+            command::READ_DATA
+            | command::FAST_READ
+            | command::PAGE_PROGRAM
+            | command::SECTOR_ERASE
+            | command::BLOCK_ERASE_32K
+            | command::BLOCK_ERASE_64K
+            | command::MANUFACTURER_DEVICE_ID => 3,
+            _ => 0,
+        };
+        if index < address_bytes {
+            self.address = (self.address << 8) | u32::from(mosi);
+            return IDLE_BYTE;
+        }
+        let index = index - address_bytes;
+
+        match command {
+            command::READ_STATUS_1 => self.status_1(),
+            command::READ_STATUS_2 | command::READ_STATUS_3 => 0,
+            command::JEDEC_ID => JEDEC_ID.get(index).copied().unwrap_or(IDLE_BYTE),
+            command::MANUFACTURER_DEVICE_ID => {
+                [JEDEC_ID[0], 0x15][(index + self.address as usize) % 2]
+            }
+            command::RELEASE_POWER_DOWN if index >= 3 => 0x15,
+            command::UNIQUE_ID if index >= 4 => {
+                UNIQUE_ID.get(index - 4).copied().unwrap_or(IDLE_BYTE)
+            }
+            command::READ_DATA => self.read_byte(),
+            command::FAST_READ if index >= 1 => self.read_byte(),
+            command::PAGE_PROGRAM => {
+                // The address wraps around within the page and only the last
+                // PAGE_SIZE bytes are kept, just like in the real chip
+                let page_offset = (self.address % PAGE_SIZE) as usize + index;
+                let page_buffer = self.page_buffer.get_or_insert([0xFF; PAGE_SIZE as usize]);
+                page_buffer[page_offset % PAGE_SIZE as usize] = mosi;
+                IDLE_BYTE
+            }
+            _ => IDLE_BYTE,
+        }
+    }
+
+    /// Execute the command of the transaction that just ended
+    fn execute(&mut self, command: u8) {
+        let reset_enabled = core::mem::replace(&mut self.reset_enabled, false);
+        let has_address = self.count >= 3;
+        let erase_size = match command {
+            command::SECTOR_ERASE if has_address => Some(SECTOR_SIZE),
+            command::BLOCK_ERASE_32K if has_address => Some(BLOCK_32K_SIZE),
+            command::BLOCK_ERASE_64K if has_address => Some(BLOCK_64K_SIZE),
+            command::CHIP_ERASE | command::CHIP_ERASE_ALT => {
+                self.address = 0;
+                Some(self.capacity() as u32)
+            }
+            _ => None,
+        };
+
+        match command {
+            command::WRITE_ENABLE => self.write_enabled = true,
+            command::WRITE_DISABLE => self.write_enabled = false,
+            command::ENABLE_RESET => self.reset_enabled = true,
+            command::RESET if reset_enabled => {
+                self.write_enabled = false;
+                self.powered_down = false;
+            }
+            command::POWER_DOWN => self.powered_down = true,
+            command::RELEASE_POWER_DOWN => self.powered_down = false,
+            command::PAGE_PROGRAM
+            | command::SECTOR_ERASE
+            | command::BLOCK_ERASE_32K
+            | command::BLOCK_ERASE_64K
+            | command::CHIP_ERASE
+            | command::CHIP_ERASE_ALT
+                if !self.write_enabled =>
+            {
+                log::warn!("W25Q32JV: command {command:#04x} ignored, write enable not set");
+            }
+            command::PAGE_PROGRAM => {
+                if let Some(page_buffer) = self.page_buffer.take() {
+                    let mut data = self.data.write().unwrap();
+                    let page = (self.address - self.address % PAGE_SIZE) as usize % data.len();
+                    for (dst, src) in data[page..].iter_mut().zip(page_buffer) {
+                        *dst &= src;
+                    }
+                }
+                self.write_enabled = false;
+            }
+            _ => {
+                if let Some(size) = erase_size {
+                    self.erase(size);
+                    self.write_enabled = false;
+                }
+            }
+        }
+    }
+
+    /// Erase the `size` sized region around the current address
+    fn erase(&mut self, size: u32) {
+        let mut data = self.data.write().unwrap();
+        let capacity = data.len();
+        let from = (self.address - self.address % size) as usize % capacity;
+        let to = (from + size as usize).min(capacity);
+        data[from..to].fill(0xFF);
+    }
+}
+
+impl SpiDeviceModel for W25q32jvSpi {
+    fn select(&mut self) {
+        self.command = None;
+        self.count = 0;
+        self.address = 0;
+        self.page_buffer = None;
+    }
+
+    fn deselect(&mut self) {
+        if let Some(command) = self.command.take() {
+            self.execute(command);
+        }
+    }
+
+    fn transfer(&mut self, mosi: u8) -> u8 {
+        let Some(command) = self.command else {
+            // Only release power down is accepted while powered down
+            if !self.powered_down || mosi == command::RELEASE_POWER_DOWN {
+                self.command = Some(mosi);
+            }
+            return IDLE_BYTE;
+        };
+
+        let miso = self.command_byte(command, self.count, mosi);
+        self.count += 1;
+        miso
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -24,8 +292,6 @@ pub enum Error {
     //PinError(P),
     NotAligned,
     OutOfBounds,
-    WriteEnableFail,
-    ReadbackFail,
 }
 
 impl NorFlashError for Error {
@@ -33,7 +299,6 @@ impl NorFlashError for Error {
         match self {
             Error::NotAligned => NorFlashErrorKind::NotAligned,
             Error::OutOfBounds => NorFlashErrorKind::OutOfBounds,
-            _ => NorFlashErrorKind::Other,
         }
     }
 }
@@ -46,9 +311,8 @@ impl SyncReadNorFlash for W25q32jv {
     const READ_SIZE: usize = 1;
 
     fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
-        let offset = offset as usize;
         let data = self.data.read().unwrap();
-        bytes.copy_from_slice(&data[offset..(offset + bytes.len())]);
+        bytes.copy_from_slice(&data[checked_range(&data, offset, bytes.len())?]);
 
         Ok(())
     }
@@ -76,29 +340,14 @@ impl SyncNorFlash for W25q32jv {
     const ERASE_SIZE: usize = SECTOR_SIZE as usize;
 
     fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
-        if !from.is_multiple_of(SECTOR_SIZE) {
-            return Err(Error::NotAligned);
-        }
-
-        if !to.is_multiple_of(SECTOR_SIZE) {
-            return Err(Error::NotAligned);
-        }
-
-        if from > to {
-            return Err(Error::OutOfBounds);
-        }
-
-        let mut data = self.data.write().unwrap();
-        data.iter_mut().for_each(|b| *b = 0xFF);
-
-        Ok(())
+        self.erase_aligned(from, to, SECTOR_SIZE)
     }
 
     fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
-        let offset = offset as usize;
         let mut data = self.data.write().unwrap();
+        let range = checked_range(&data, offset, bytes.len())?;
 
-        for (dst, src) in data[offset..].iter_mut().zip(bytes) {
+        for (dst, src) in data[range].iter_mut().zip(bytes) {
             *dst &= src;
         }
 
@@ -173,7 +422,7 @@ mod test {
         assert_eq!(flash.erase(0, 2), Err(Error::NotAligned));
         assert_eq!(flash.erase(1, SECTOR_SIZE), Err(Error::NotAligned));
         assert_eq!(flash.erase(SECTOR_SIZE, 0), Err(Error::OutOfBounds));
-        //assert_eq!(flash.erase(0, 2 * SECTOR_SIZE), Err(Error::OutOfBounds));
+        assert_eq!(flash.erase(0, 2 * SECTOR_SIZE), Err(Error::OutOfBounds));
 
         {
             // single reads write, bulk read
@@ -188,4 +437,106 @@ mod test {
             assert_eq!(read_bytes, bytes_to_write);
         }
     }
+
+    #[test]
+    fn erase() {
+        use crate::flash::w25q32jv::{BLOCK_32K_SIZE, BLOCK_64K_SIZE};
+
+        let capacity = 2 * BLOCK_64K_SIZE;
+        let data = Arc::new(RwLock::new(vec![0u8; capacity as usize].into_boxed_slice()));
+        let mut flash = W25q32jv::new(Arc::clone(&data));
+        let all = |range: std::ops::Range<u32>, value: u8| {
+            let data = data.read().unwrap();
+            data[range.start as usize..range.end as usize]
+                .iter()
+                .all(|&b| b == value)
+        };
+
+        // Only the given sectors are erased
+        flash.erase(SECTOR_SIZE, 3 * SECTOR_SIZE).unwrap();
+        assert!(all(0..SECTOR_SIZE, 0));
+        assert!(all(SECTOR_SIZE..3 * SECTOR_SIZE, 0xFF));
+        assert!(all(3 * SECTOR_SIZE..capacity, 0));
+
+        flash.erase_block_32k(BLOCK_32K_SIZE).unwrap();
+        assert!(all(BLOCK_32K_SIZE..BLOCK_64K_SIZE, 0xFF));
+        assert!(all(BLOCK_64K_SIZE..capacity, 0));
+        flash.erase_block_64k(BLOCK_64K_SIZE).unwrap();
+        assert!(all(BLOCK_64K_SIZE..capacity, 0xFF));
+        assert_eq!(flash.erase_block_64k(SECTOR_SIZE), Err(Error::NotAligned));
+        assert_eq!(flash.erase_block_64k(capacity), Err(Error::OutOfBounds));
+        assert_eq!(
+            flash.erase_block_64k(u32::MAX - BLOCK_64K_SIZE + 1),
+            Err(Error::OutOfBounds)
+        );
+
+        // Out of bounds accesses fail instead of panicking
+        let mut dst = [0; 2];
+        assert_eq!(flash.read(capacity - 1, &mut dst), Err(Error::OutOfBounds));
+        assert_eq!(flash.write(capacity - 1, &[0, 0]), Err(Error::OutOfBounds));
+        assert_eq!(flash.write(u32::MAX, &[0]), Err(Error::OutOfBounds));
+        flash.read(capacity - 2, &mut dst).unwrap();
+
+        flash.erase_chip().unwrap();
+        assert!(all(0..capacity, 0xFF));
+    }
+
+    #[test]
+    fn spi() {
+        use crate::flash::w25q32jv::{JEDEC_ID, PAGE_SIZE, STATUS_WEL, W25q32jvSpi};
+        use crate::{gpio, spi::SpiDevice};
+        use embedded_hal::digital::PinState;
+        use embedded_hal::spi::{Operation, SpiDevice as _};
+
+        let data = vec![0xFFu8; 2 * SECTOR_SIZE as usize].into_boxed_slice();
+        let data = Arc::new(RwLock::new(data));
+        let mut flash = W25q32jv::new(Arc::clone(&data));
+        let (_cs, cs) = gpio::new(PinState::High);
+        let (mut spi, _) = SpiDevice::new(cs, W25q32jvSpi::new(Arc::clone(&data)));
+
+        let mut id = [0x9F, 0, 0, 0];
+        spi.transfer_in_place(&mut id).unwrap();
+        assert_eq!(id[1..], JEDEC_ID);
+
+        // Page program is ignored without write enable
+        spi.write(&[0x02, 0x00, 0x10, 0x00, 0x12]).unwrap();
+        let mut dst = [0; 1];
+        flash.read(SECTOR_SIZE, &mut dst).unwrap();
+        assert_eq!(dst, [0xFF]);
+
+        spi.write(&[0x06]).unwrap();
+        let mut status = [0x05, 0];
+        spi.transfer_in_place(&mut status).unwrap();
+        assert_eq!(status[1] & STATUS_WEL, STATUS_WEL);
+
+        // Program wraps around within the page
+        spi.write(&[0x02, 0x00, 0x10, 0xFE, 1, 2, 3, 4]).unwrap();
+        let mut status = [0x05, 0];
+        spi.transfer_in_place(&mut status).unwrap();
+        assert_eq!(status[1] & STATUS_WEL, 0);
+
+        let mut dst = [0; 2];
+        flash.read(SECTOR_SIZE, &mut dst).unwrap();
+        assert_eq!(dst, [3, 4]);
+        flash.read(SECTOR_SIZE + PAGE_SIZE - 2, &mut dst).unwrap();
+        assert_eq!(dst, [1, 2]);
+
+        let mut read = [0; 4];
+        spi.transaction(&mut [
+            Operation::Write(&[0x03, 0x00, 0x10, 0x00]),
+            Operation::Read(&mut read),
+        ])
+        .unwrap();
+        assert_eq!(read, [3, 4, 0xFF, 0xFF]);
+
+        // Sector erase only affects the addressed sector
+        flash.write(0, &[0x55]).unwrap();
+        spi.write(&[0x06]).unwrap();
+        spi.write(&[0x20, 0x00, 0x10, 0x23]).unwrap();
+        let mut dst = [0; 2];
+        flash.read(SECTOR_SIZE, &mut dst).unwrap();
+        assert_eq!(dst, [0xFF, 0xFF]);
+        flash.read(0, &mut dst[..1]).unwrap();
+        assert_eq!(dst[0], 0x55);
+    }
 }
@@ -0,0 +1,307 @@
+use embedded_hal::digital::{InputPin, PinState};
+use parking_lot::Mutex;
+use std::sync::Arc;
+
+use super::{Drive, Input, Listener, Net, Observe, Pull, net::PinMode};
+use crate::utils::{SignalRx, SignalTx};
+
+/// Direction of a [Flex] pin
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Input(Pull),
+    Output(Drive),
+}
+
+/// A bidirectional pin whose direction can be changed at runtime
+///
+/// As an input it reads the net like an [Input], as an output it drives the net like
+/// an [super::Output]. The output level can be set while in input mode and is applied
+/// once the pin is switched to output. An open-drain output still reads the net, which
+/// is how e.g. bit-banged i2c and 1-Wire are done.
+pub struct Flex {
+    /// Also the pin driving the net while in output mode
+    input: Input,
+    /// Shared with the stimulus, which must not look at the pin once it is dropped
+    direction: Arc<Mutex<Direction>>,
+    state: PinState,
+    w: SignalTx<Direction>,
+}
+
+/// Stimulus side of a [Flex] pin, see [super::flex]
+///
+/// Attach pins to [FlexStimulus::net] to drive the line while the application reads it.
+#[derive(Clone)]
+pub struct FlexStimulus {
+    net: Net,
+    direction: Arc<Mutex<Direction>>,
+    w: SignalRx<Direction>,
+}
+
+/// A [Flex] pin starting out as an input, together with its stimulus side
+///
+/// `pull` is an external pull resistor on the line, independent of the direction of the pin.
+///
+/// ```
+/// # use embedded_hal::digital::{InputPin, OutputPin, PinState};
+/// # use embedded_hal_sim::gpio::{self, Direction, Drive, Pull};
+/// let (mut pin, stimulus) = gpio::flex(Pull::Up);
+/// pin.set_low().unwrap();
+/// pin.set_as_output(Drive::OpenDrain);
+/// assert_eq!(stimulus.direction(), Direction::Output(Drive::OpenDrain));
+///
+/// // The sensor answers once the application releases the line
+/// pin.set_as_input(Pull::None);
+/// let mut sensor = stimulus.net().output(Drive::OpenDrain, PinState::Low);
+/// assert!(pin.is_low().unwrap());
+/// sensor.set_high().unwrap();
+/// assert!(pin.is_high().unwrap());
+/// ```
+pub fn flex(pull: Pull) -> (Flex, FlexStimulus) {
+    let net = Net::new();
+    net.attach(PinMode::Input(pull));
+    net.flex()
+}
+
+impl Flex {
+    pub(crate) fn new(net: &Net) -> (Self, FlexStimulus) {
+        let direction = Arc::new(Mutex::new(Direction::Input(Pull::None)));
+        let (tx, rx) = crate::utils::signal(Direction::Input(Pull::None));
+        (
+            Self {
+                input: net.input(Pull::None),
+                direction: Arc::clone(&direction),
+                state: PinState::Low,
+                w: tx,
+            },
+            FlexStimulus {
+                net: net.clone(),
+                direction,
+                w: rx,
+            },
+        )
+    }
+
+    pub fn set_as_input(&mut self, pull: Pull) {
+        self.set_direction(Direction::Input(pull));
+    }
+
+    pub fn set_as_output(&mut self, drive: Drive) {
+        self.set_direction(Direction::Output(drive));
+    }
+
+    pub fn direction(&self) -> Direction {
+        *self.direction.lock()
+    }
+
+    /// The net this pin is attached to
+    pub fn net(&self) -> &Net {
+        self.input.net()
+    }
+
+    fn set_direction(&mut self, direction: Direction) {
+        *self.direction.lock() = direction;
+        self.update();
+        self.w.signal(direction);
+    }
+
+    fn update(&mut self) {
+        let mode = match self.direction() {
+            Direction::Input(pull) => PinMode::Input(pull),
+            Direction::Output(drive) => PinMode::Output(drive, self.state),
+        };
+        self.input.net.set_mode(self.input.pin, mode);
+    }
+}
+
+impl Drop for Flex {
+    fn drop(&mut self) {
+        // The input detaches the pin from the net
+        *self.direction.lock() = Direction::Input(Pull::None);
+        self.w.signal(Direction::Input(Pull::None));
+    }
+}
+
+impl Observe for Flex {
+    fn level(&self) -> PinState {
+        self.input.level()
+    }
+
+    fn on_change(&self, listener: impl FnMut(PinState) + Send + 'static) -> Listener {
+        self.input.on_change(listener)
+    }
+}
+
+impl embedded_hal::digital::ErrorType for Flex {
+    type Error = core::convert::Infallible;
+}
+
+impl InputPin for Flex {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        self.input.is_high()
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        self.input.is_low()
+    }
+}
+
+impl embedded_hal::digital::OutputPin for Flex {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.state = PinState::Low;
+        self.update();
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.state = PinState::High;
+        self.update();
+        Ok(())
+    }
+}
+
+impl embedded_hal::digital::StatefulOutputPin for Flex {
+    fn is_set_high(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.state == PinState::High)
+    }
+
+    fn is_set_low(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.state == PinState::Low)
+    }
+}
+
+impl embedded_hal_async::digital::Wait for Flex {
+    async fn wait_for_high(&mut self) -> Result<(), Self::Error> {
+        self.input.wait_for_high().await
+    }
+
+    async fn wait_for_low(&mut self) -> Result<(), Self::Error> {
+        self.input.wait_for_low().await
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> Result<(), Self::Error> {
+        self.input.wait_for_rising_edge().await
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> Result<(), Self::Error> {
+        self.input.wait_for_falling_edge().await
+    }
+
+    async fn wait_for_any_edge(&mut self) -> Result<(), Self::Error> {
+        self.input.wait_for_any_edge().await
+    }
+}
+
+impl FlexStimulus {
+    /// The current direction of the [Flex] pin
+    pub fn direction(&self) -> Direction {
+        *self.direction.lock()
+    }
+
+    /// Wait for the application to change the direction of the pin
+    ///
+    /// Returns `None` once the [Flex] pin is dropped.
+    pub async fn wait_for_direction_change(&mut self) -> Option<Direction> {
+        self.w.wait().await
+    }
+
+    /// Wait until the pin is an input, e.g. before answering a request in a bit-banged protocol
+    ///
+    /// A dropped pin counts as an input without pull.
+    pub async fn wait_for_input(&mut self) -> Pull {
+        loop {
+            if let Direction::Input(pull) = self.direction() {
+                return pull;
+            }
+            if self.wait_for_direction_change().await.is_none() {
+                return Pull::None;
+            }
+        }
+    }
+
+    /// Wait until the pin is an output, `None` if the [Flex] pin is dropped first
+    pub async fn wait_for_output(&mut self) -> Option<Drive> {
+        loop {
+            if let Direction::Output(drive) = self.direction() {
+                return Some(drive);
+            }
+            self.wait_for_direction_change().await?;
+        }
+    }
+
+    /// The net shared with the [Flex] pin
+    pub fn net(&self) -> &Net {
+        &self.net
+    }
+}
+
+impl Observe for FlexStimulus {
+    fn level(&self) -> PinState {
+        self.net.level()
+    }
+
+    fn on_change(&self, listener: impl FnMut(PinState) + Send + 'static) -> Listener {
+        self.net.on_change(listener)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Direction;
+    use crate::gpio::{self, Drive, Pull};
+    use embedded_hal::digital::{OutputPin, PinState};
+    use embedded_hal_async::digital::Wait;
+    use futures::FutureExt;
+    use std::pin::pin;
+
+    #[test]
+    fn wait() {
+        let (mut pin, stimulus) = gpio::flex(Pull::Up);
+        let mut sensor = stimulus.net().output(Drive::OpenDrain, PinState::High);
+        assert!(pin.wait_for_high().now_or_never().is_some());
+
+        {
+            let mut falling = pin!(pin.wait_for_falling_edge());
+            assert!(falling.as_mut().now_or_never().is_none());
+            sensor.set_low().unwrap();
+            assert!(falling.now_or_never().is_some());
+        }
+        assert!(pin.wait_for_low().now_or_never().is_some());
+
+        // Driving the line itself, the pin sees its own edges
+        sensor.set_high().unwrap();
+        assert!(pin.wait_for_rising_edge().now_or_never().is_some());
+        pin.set_low().unwrap();
+        pin.set_as_output(Drive::OpenDrain);
+        assert!(pin.wait_for_any_edge().now_or_never().is_some());
+        pin.set_as_input(Pull::None);
+        assert!(pin.wait_for_rising_edge().now_or_never().is_some());
+    }
+
+    #[test]
+    fn direction() {
+        let (mut pin, mut stimulus) = gpio::flex(Pull::None);
+        assert_eq!(stimulus.wait_for_input().now_or_never(), Some(Pull::None));
+
+        {
+            let mut output = pin!(stimulus.wait_for_output());
+            assert!(output.as_mut().now_or_never().is_none());
+            pin.set_as_output(Drive::PushPull);
+            assert_eq!(output.now_or_never(), Some(Some(Drive::PushPull)));
+        }
+        {
+            let mut change = pin!(stimulus.wait_for_direction_change());
+            assert!(change.as_mut().now_or_never().is_none());
+            pin.set_as_input(Pull::Down);
+            assert_eq!(
+                change.now_or_never(),
+                Some(Some(Direction::Input(Pull::Down)))
+            );
+        }
+
+        pin.set_as_output(Drive::OpenDrain);
+        drop(pin);
+        assert_eq!(stimulus.direction(), Direction::Input(Pull::None));
+        assert_eq!(stimulus.wait_for_input().now_or_never(), Some(Pull::None));
+        assert_eq!(stimulus.wait_for_output().now_or_never(), Some(None));
+    }
+}
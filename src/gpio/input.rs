@@ -1,10 +1,185 @@
 use embedded_hal::digital::{InputPin, PinState};
-use std::sync::{Arc, atomic::AtomicBool};
+use std::{
+    collections::VecDeque,
+    future::poll_fn,
+    task::{Poll, Waker},
+};
+
+use super::{Listener, Net, Observe, Pull, net::PinMode};
+
+/// Number of edges an [Input] keeps by default, see [Input::set_queue_depth]
+pub const DEFAULT_QUEUE_DEPTH: usize = 16;
 
-use crate::utils::SignalRx;
 pub struct Input {
-    pub(crate) state: Arc<AtomicBool>,
-    pub(crate) w: SignalRx<PinState>,
+    pub(crate) net: Net,
+    pub(crate) pin: usize,
+    pub(crate) queue: usize,
+}
+
+/// Every level change of the net seen by one input, oldest first
+pub(crate) struct EdgeQueue {
+    edges: VecDeque<PinState>,
+    depth: usize,
+    overflow: bool,
+    /// Number of edges ever pushed, and the number of the last one to high and to low
+    count: u64,
+    last_high: u64,
+    last_low: u64,
+    waker: Option<Waker>,
+}
+
+impl Default for EdgeQueue {
+    fn default() -> Self {
+        Self {
+            edges: VecDeque::new(),
+            depth: DEFAULT_QUEUE_DEPTH,
+            overflow: false,
+            count: 0,
+            last_high: 0,
+            last_low: 0,
+            waker: None,
+        }
+    }
+}
+
+impl EdgeQueue {
+    /// Record that the net changed to `level`
+    pub(crate) fn push(&mut self, level: PinState) {
+        if self.edges.len() >= self.depth {
+            self.edges.pop_front();
+            self.overflow = true;
+        }
+        self.edges.push_back(level);
+        self.count += 1;
+        match level {
+            PinState::High => self.last_high = self.count,
+            PinState::Low => self.last_low = self.count,
+        }
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// Consume edges up to and including the first one to `level`
+    fn take_until(&mut self, level: PinState) -> bool {
+        while let Some(edge) = self.edges.pop_front() {
+            if edge == level {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+impl Input {
+    /// The net this input is attached to
+    pub fn net(&self) -> &Net {
+        &self.net
+    }
+
+    /// Set how many edges are kept for the `wait_for_*_edge` functions
+    ///
+    /// Just like an interrupt flag that is set while the application is busy, edges
+    /// happening when nobody waits are kept and returned by the next wait. When more
+    /// than `depth` edges are pending the oldest ones are lost, see [Input::take_overflow].
+    pub fn set_queue_depth(&mut self, depth: usize) {
+        self.net.with_queue(self.queue, |queue, _| {
+            queue.depth = depth.max(1);
+            while queue.edges.len() > queue.depth {
+                queue.edges.pop_front();
+                queue.overflow = true;
+            }
+        })
+    }
+
+    /// Returns true if edges were lost since the last call
+    pub fn take_overflow(&mut self) -> bool {
+        self.net
+            .with_queue(self.queue, |queue, _| core::mem::take(&mut queue.overflow))
+    }
+
+    /// Number of edges that have not been consumed by a `wait_for_*` function yet
+    pub fn pending_edges(&self) -> usize {
+        self.net
+            .with_queue(self.queue, |queue, _| queue.edges.len())
+    }
+
+    /// Forget every pending edge
+    pub fn clear_edges(&mut self) {
+        self.net
+            .with_queue(self.queue, |queue, _| queue.edges.clear())
+    }
+
+    /// Wait for the net to be at `level`, the pending edges are left for the edge waits
+    async fn wait_for_level(&mut self, level: PinState) {
+        let mut start = None;
+        poll_fn(|cx| {
+            self.net.with_queue(self.queue, |queue, current| {
+                let start = *start.get_or_insert(queue.count);
+                let last = match level {
+                    PinState::High => queue.last_high,
+                    PinState::Low => queue.last_low,
+                };
+                // A short pulse during the wait that has already ended still counts
+                if current == level || last > start {
+                    return Poll::Ready(());
+                }
+                queue.waker = Some(cx.waker().clone());
+                Poll::Pending
+            })
+        })
+        .await
+    }
+
+    async fn wait_for_edge(&mut self, level: Option<PinState>) {
+        poll_fn(|cx| {
+            self.net.with_queue(self.queue, |queue, _| {
+                let found = match level {
+                    Some(level) => queue.take_until(level),
+                    None => queue.edges.pop_front().is_some(),
+                };
+                if found {
+                    return Poll::Ready(());
+                }
+                queue.waker = Some(cx.waker().clone());
+                Poll::Pending
+            })
+        })
+        .await
+    }
+}
+
+impl Clone for Input {
+    /// Subscribe another input to the same net
+    ///
+    /// The new input has the same pull and queue depth but starts without pending edges.
+    fn clone(&self) -> Self {
+        let pull = match self.net.mode(self.pin) {
+            Some(PinMode::Input(pull)) => pull,
+            _ => Pull::None,
+        };
+        let mut input = self.net.input(pull);
+        let depth = self.net.with_queue(self.queue, |queue, _| queue.depth);
+        input.set_queue_depth(depth);
+        input
+    }
+}
+
+impl Observe for Input {
+    fn level(&self) -> PinState {
+        self.net.level()
+    }
+
+    fn on_change(&self, listener: impl FnMut(PinState) + Send + 'static) -> Listener {
+        self.net.on_change(listener)
+    }
+}
+
+impl Drop for Input {
+    fn drop(&mut self) {
+        self.net.detach(self.pin);
+        self.net.remove_queue(self.queue);
+    }
 }
 
 impl embedded_hal::digital::ErrorType for Input {
@@ -13,7 +188,7 @@ impl embedded_hal::digital::ErrorType for Input {
 
 impl InputPin for Input {
     fn is_high(&mut self) -> Result<bool, Self::Error> {
-        Ok(self.state.load(std::sync::atomic::Ordering::SeqCst))
+        Ok(self.net.level() == PinState::High)
     }
 
     fn is_low(&mut self) -> Result<bool, Self::Error> {
@@ -23,41 +198,116 @@ impl InputPin for Input {
 
 impl embedded_hal_async::digital::Wait for Input {
     async fn wait_for_high(&mut self) -> Result<(), Self::Error> {
-        if self.is_high()? {
-            return Ok(());
-        }
-        loop {
-            if self.w.wait().await == PinState::High {
-                return Ok(());
-            }
-        }
+        self.wait_for_level(PinState::High).await;
+        Ok(())
     }
 
     async fn wait_for_low(&mut self) -> Result<(), Self::Error> {
-        if self.is_low()? {
-            return Ok(());
-        }
-        loop {
-            if self.w.wait().await == PinState::Low {
-                return Ok(());
-            }
-        }
+        self.wait_for_level(PinState::Low).await;
+        Ok(())
     }
 
     async fn wait_for_rising_edge(&mut self) -> Result<(), Self::Error> {
-        self.wait_for_low().await?;
-        self.wait_for_high().await
+        self.wait_for_edge(Some(PinState::High)).await;
+        Ok(())
     }
 
     async fn wait_for_falling_edge(&mut self) -> Result<(), Self::Error> {
-        self.wait_for_high().await?;
-        self.wait_for_low().await
+        self.wait_for_edge(Some(PinState::Low)).await;
+        Ok(())
     }
 
     async fn wait_for_any_edge(&mut self) -> Result<(), Self::Error> {
-        match self.is_high()? {
-            true => self.wait_for_low().await,
-            false => self.wait_for_high().await,
+        self.wait_for_edge(None).await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{gpio, time::VirtualClock};
+    use embedded_hal::digital::{OutputPin, PinState};
+    use embedded_hal_async::digital::Wait;
+    use futures::FutureExt;
+    use std::{pin::pin, time::Duration};
+
+    #[test]
+    fn edges() {
+        let clock = VirtualClock::new();
+        let (mut input, mut output) = gpio::new(PinState::Low);
+
+        // Pulses shorter than the time between polls are all seen
+        let pulses = clock.block_on(async {
+            let count = async {
+                let mut pulses = 0;
+                while crate::time::timeout(Duration::from_secs(1), input.wait_for_falling_edge())
+                    .await
+                    .is_ok()
+                {
+                    pulses += 1;
+                }
+                pulses
+            };
+            let toggle = async {
+                for _ in 0..5 {
+                    output.set_high().unwrap();
+                    output.set_low().unwrap();
+                }
+                crate::sleep(Duration::from_millis(1)).await;
+                output.set_high().unwrap();
+                output.set_low().unwrap();
+            };
+            futures::join!(count, toggle).0
+        });
+        assert_eq!(pulses, 6);
+        assert!(!input.take_overflow());
+
+        input.set_queue_depth(4);
+        for _ in 0..3 {
+            output.set_high().unwrap();
+            output.set_low().unwrap();
         }
+        assert_eq!(input.pending_edges(), 4);
+        assert!(input.take_overflow());
+        assert!(!input.take_overflow());
+    }
+
+    #[test]
+    fn subscribers() {
+        let clock = VirtualClock::new();
+        let (mut firmware, mut output) = gpio::new(PinState::Low);
+        let mut ui = firmware.clone();
+        let mut logger = output.subscribe();
+
+        clock.block_on(async {
+            let toggle = async {
+                crate::sleep(Duration::from_millis(1)).await;
+                output.set_high().unwrap();
+            };
+            futures::join!(
+                firmware.wait_for_rising_edge(),
+                ui.wait_for_rising_edge(),
+                logger.wait_for_high(),
+                toggle
+            );
+        });
+    }
+
+    #[test]
+    fn levels() {
+        let (mut input, mut output) = gpio::new(PinState::Low);
+        output.set_high().unwrap();
+        output.set_low().unwrap();
+
+        // The pulse before the wait does not count, the edges are kept
+        assert!(input.wait_for_high().now_or_never().is_none());
+        assert!(input.wait_for_low().now_or_never().is_some());
+        assert_eq!(input.pending_edges(), 2);
+
+        let mut high = pin!(input.wait_for_high());
+        assert!(high.as_mut().now_or_never().is_none());
+        output.set_high().unwrap();
+        output.set_low().unwrap();
+        assert!(high.now_or_never().is_some());
     }
 }
@@ -1,22 +1,33 @@
 use embedded_hal::digital::PinState;
-use std::sync::{Arc, atomic::AtomicBool};
 
-pub use input::Input;
+pub use flex::{Direction, Flex, FlexStimulus, flex};
+pub use input::{DEFAULT_QUEUE_DEPTH, Input};
+pub use net::{Drive, Listener, Net, Pull};
 pub use output::Output;
 
-use crate::utils;
-
+mod flex;
 mod input;
+mod net;
 mod output;
 
+/// A push-pull output connected to a single input
 pub fn new(initial_state: PinState) -> (Input, Output) {
-    let state = Arc::new(AtomicBool::new(initial_state == PinState::High));
-    let (tx, rx) = utils::signal(initial_state);
-    (
-        Input {
-            state: Arc::clone(&state),
-            w: rx,
-        },
-        Output { state, w: tx },
-    )
+    let net = Net::new();
+    let output = net.output(Drive::PushPull, initial_state);
+    (net.input(Pull::None), output)
+}
+
+/// A pin whose level changes can be observed, e.g. by a [crate::vcd::Recorder]
+///
+/// Every pin attached to a [Net] sees the same changes.
+pub trait Observe {
+    /// The current level of the line
+    fn level(&self) -> PinState;
+
+    /// Call `listener` with the new level every time the line changes
+    ///
+    /// The listener runs in the context of the code changing the level, while the
+    /// net is locked, so it must not access the pins of the same net. It keeps being
+    /// called until removed through the returned [Listener].
+    fn on_change(&self, listener: impl FnMut(PinState) + Send + 'static) -> Listener;
 }
@@ -0,0 +1,337 @@
+use embedded_hal::digital::PinState;
+use parking_lot::Mutex;
+use std::sync::{Arc, Weak};
+
+use super::{Flex, FlexStimulus, Input, Observe, Output, input::EdgeQueue};
+
+/// How an output drives its net
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Drive {
+    /// Actively drives both high and low
+    PushPull,
+    /// Only drives low, setting the pin high releases the net
+    OpenDrain,
+}
+
+/// Weak pull resistor of an input
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Pull {
+    #[default]
+    None,
+    Up,
+    Down,
+}
+
+/// What a single pin attached to a net contributes to its level
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PinMode {
+    Output(Drive, PinState),
+    Input(Pull),
+}
+
+/// A wire connecting any number of pins
+///
+/// The level of the net is resolved from every attached pin. Any pin driving the net
+/// low wins over pull resistors, and so does a push-pull output driving it high.
+/// If no pin drives the net, the pull resistors decide, and a net without drivers or
+/// with conflicting pulls is floating and keeps its last level.
+///
+/// A push-pull output driving high while another pin drives low is contention. This is
+/// reported through the log and [Net::has_contention], and the net reads as low.
+///
+/// ```
+/// # use embedded_hal::digital::{InputPin, OutputPin, PinState};
+/// # use embedded_hal_sim::gpio::{Drive, Net, Pull};
+/// // A shared interrupt line with a pull-up
+/// let irq = Net::new();
+/// let mut mcu = irq.input(Pull::Up);
+/// let mut sensor_a = irq.output(Drive::OpenDrain, PinState::High);
+/// let mut sensor_b = irq.output(Drive::OpenDrain, PinState::High);
+/// assert!(mcu.is_high().unwrap());
+///
+/// sensor_b.set_low().unwrap();
+/// assert!(mcu.is_low().unwrap());
+/// ```
+#[derive(Clone, Default)]
+pub struct Net {
+    inner: Arc<Mutex<NetState>>,
+}
+
+type ListenerFn = Box<dyn FnMut(PinState) + Send>;
+
+#[derive(Default)]
+struct NetState {
+    level: bool,
+    contention: bool,
+    floating: bool,
+    pins: Vec<Option<PinMode>>,
+    /// Slots of dropped pins, reused by the next pin attached
+    free_pins: Vec<usize>,
+    listeners: Vec<Option<ListenerFn>>,
+    free_listeners: Vec<usize>,
+    queues: Vec<Option<EdgeQueue>>,
+    free_queues: Vec<usize>,
+}
+
+impl Net {
+    /// A floating net reading low
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Attach an output to the net
+    pub fn output(&self, drive: Drive, initial_state: PinState) -> Output {
+        let pin = self.attach(PinMode::Output(drive, initial_state));
+        Output {
+            net: self.clone(),
+            pin,
+            drive,
+            state: initial_state,
+        }
+    }
+
+    /// Attach a [Flex] pin to the net, it starts out as an input without pull
+    ///
+    /// The stimulus side sees the direction changes of the pin.
+    pub fn flex(&self) -> (Flex, FlexStimulus) {
+        Flex::new(self)
+    }
+
+    /// Attach an input to the net
+    pub fn input(&self, pull: Pull) -> Input {
+        let mut state = self.inner.lock();
+        let pin = state.attach(PinMode::Input(pull));
+        let state = &mut *state;
+        let queue = insert(
+            &mut state.queues,
+            &mut state.free_queues,
+            EdgeQueue::default(),
+        );
+        Input {
+            net: self.clone(),
+            pin,
+            queue,
+        }
+    }
+
+    pub fn level(&self) -> PinState {
+        PinState::from(self.inner.lock().level)
+    }
+
+    /// The net is driven both high and low at the same time
+    pub fn has_contention(&self) -> bool {
+        self.inner.lock().contention
+    }
+
+    /// Nothing drives the net and no pull resistor decides its level
+    pub fn is_floating(&self) -> bool {
+        self.inner.lock().floating
+    }
+
+    pub(crate) fn attach(&self, mode: PinMode) -> usize {
+        self.inner.lock().attach(mode)
+    }
+
+    pub(crate) fn set_mode(&self, pin: usize, mode: PinMode) {
+        let mut state = self.inner.lock();
+        state.pins[pin] = Some(mode);
+        state.resolve();
+    }
+
+    /// Remove a dropped pin from the net
+    pub(crate) fn detach(&self, pin: usize) {
+        let mut state = self.inner.lock();
+        state.pins[pin] = None;
+        state.free_pins.push(pin);
+        state.resolve();
+    }
+
+    pub(crate) fn mode(&self, pin: usize) -> Option<PinMode> {
+        self.inner.lock().pins[pin]
+    }
+
+    pub(crate) fn remove_queue(&self, queue: usize) {
+        let mut state = self.inner.lock();
+        state.queues[queue] = None;
+        state.free_queues.push(queue);
+    }
+
+    /// Run `f` on the edge queue of an input together with the current level of the net
+    pub(crate) fn with_queue<R>(
+        &self,
+        queue: usize,
+        f: impl FnOnce(&mut EdgeQueue, PinState) -> R,
+    ) -> R {
+        let mut state = self.inner.lock();
+        let level = PinState::from(state.level);
+        f(state.queues[queue].as_mut().unwrap(), level)
+    }
+}
+
+/// Put `value` into a free slot, or a new one if there is none
+fn insert<T>(slots: &mut Vec<Option<T>>, free: &mut Vec<usize>, value: T) -> usize {
+    match free.pop() {
+        Some(index) => {
+            slots[index] = Some(value);
+            index
+        }
+        None => {
+            slots.push(Some(value));
+            slots.len() - 1
+        }
+    }
+}
+
+impl NetState {
+    fn attach(&mut self, mode: PinMode) -> usize {
+        let pin = insert(&mut self.pins, &mut self.free_pins, mode);
+        self.resolve();
+        pin
+    }
+
+    fn resolve(&mut self) {
+        let pins = self.pins.iter().flatten();
+        let driven_low = pins
+            .clone()
+            .any(|mode| matches!(mode, PinMode::Output(_, PinState::Low)));
+        let driven_high = pins
+            .clone()
+            .any(|mode| *mode == PinMode::Output(Drive::PushPull, PinState::High));
+        let pulled_up = pins.clone().any(|mode| *mode == PinMode::Input(Pull::Up));
+        let pulled_down = pins.clone().any(|mode| *mode == PinMode::Input(Pull::Down));
+
+        let contention = driven_low && driven_high;
+        if contention && !self.contention {
+            log::warn!("gpio net driven both high and low");
+        }
+        self.contention = contention;
+        self.floating = !driven_low && !driven_high && pulled_up == pulled_down;
+
+        let level = match (driven_low, driven_high) {
+            (true, _) => false,
+            (false, true) => true,
+            (false, false) if self.floating => self.level,
+            (false, false) => pulled_up,
+        };
+
+        if level != self.level {
+            self.level = level;
+            let level = PinState::from(level);
+            self.listeners
+                .iter_mut()
+                .flatten()
+                .for_each(|listener| listener(level));
+            self.queues
+                .iter_mut()
+                .flatten()
+                .for_each(|queue| queue.push(level));
+        }
+    }
+}
+
+impl Observe for Net {
+    fn level(&self) -> PinState {
+        Net::level(self)
+    }
+
+    fn on_change(&self, listener: impl FnMut(PinState) + Send + 'static) -> Listener {
+        let mut state = self.inner.lock();
+        let state = &mut *state;
+        let index = insert(
+            &mut state.listeners,
+            &mut state.free_listeners,
+            Box::new(listener),
+        );
+        Listener {
+            net: Arc::downgrade(&self.inner),
+            index,
+        }
+    }
+}
+
+/// A listener registered with [Observe::on_change]
+///
+/// Dropping this keeps the listener registered, call [Listener::remove] to stop it.
+pub struct Listener {
+    net: Weak<Mutex<NetState>>,
+    index: usize,
+}
+
+impl Listener {
+    /// Stop calling the listener
+    pub fn remove(self) {
+        if let Some(net) = self.net.upgrade() {
+            let mut state = net.lock();
+            state.listeners[self.index] = None;
+            state.free_listeners.push(self.index);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Drive, Net, Pull};
+    use crate::gpio::Observe;
+    use embedded_hal::digital::{InputPin, OutputPin, PinState, StatefulOutputPin};
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+
+    #[test]
+    fn resolve() {
+        let net = Net::new();
+        let mut input = net.input(Pull::None);
+        assert!(net.is_floating());
+
+        let mut push_pull = net.output(Drive::PushPull, PinState::High);
+        let mut open_drain = net.output(Drive::OpenDrain, PinState::High);
+        assert!(input.is_high().unwrap());
+        assert!(!net.has_contention());
+
+        open_drain.set_low().unwrap();
+        assert!(net.has_contention());
+        assert!(input.is_low().unwrap());
+        assert!(push_pull.is_set_high().unwrap());
+
+        // A floating net keeps its level until pulled
+        drop(push_pull);
+        open_drain.set_high().unwrap();
+        assert!(!net.has_contention());
+        assert!(net.is_floating());
+        assert!(input.is_low().unwrap());
+
+        let _pull_up = net.input(Pull::Up);
+        assert!(input.is_high().unwrap());
+    }
+
+    #[test]
+    fn reuse_slots() {
+        let net = Net::new();
+        let _input = net.input(Pull::None);
+        for _ in 0..10 {
+            drop(net.input(Pull::Up));
+            drop(net.output(Drive::PushPull, PinState::High));
+        }
+        let state = net.inner.lock();
+        assert_eq!(state.pins.len(), 2);
+        assert_eq!(state.queues.len(), 2);
+    }
+
+    #[test]
+    fn listener() {
+        let net = Net::new();
+        let mut output = net.output(Drive::PushPull, PinState::Low);
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let listener = net.on_change({
+            let changes = changes.clone();
+            move |level| changes.lock().push(level)
+        });
+
+        output.set_high().unwrap();
+        listener.remove();
+        output.set_low().unwrap();
+        assert_eq!(*changes.lock(), [PinState::High]);
+
+        net.on_change(|_| ()).remove();
+        assert_eq!(net.inner.lock().listeners.len(), 1);
+    }
+}
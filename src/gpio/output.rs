@@ -1,13 +1,48 @@
-use crate::utils::SignalTx;
 use embedded_hal::digital::PinState;
-use std::sync::{
-    Arc,
-    atomic::{AtomicBool, Ordering},
-};
+
+use super::{Drive, Input, Listener, Net, Observe, Pull, net::PinMode};
 
 pub struct Output {
-    pub(crate) state: Arc<AtomicBool>,
-    pub(crate) w: SignalTx<PinState>,
+    pub(crate) net: Net,
+    pub(crate) pin: usize,
+    pub(crate) drive: Drive,
+    pub(crate) state: PinState,
+}
+
+impl Output {
+    fn set(&mut self, state: PinState) {
+        self.state = state;
+        self.net
+            .set_mode(self.pin, PinMode::Output(self.drive, state));
+    }
+
+    /// A new input observing this output
+    ///
+    /// Any number of inputs can be attached, e.g. for the firmware, the UI and a logger.
+    pub fn subscribe(&self) -> Input {
+        self.net.input(Pull::None)
+    }
+
+    /// The net this output is attached to
+    pub fn net(&self) -> &Net {
+        &self.net
+    }
+}
+
+impl Observe for Output {
+    fn level(&self) -> PinState {
+        self.net.level()
+    }
+
+    fn on_change(&self, listener: impl FnMut(PinState) + Send + 'static) -> Listener {
+        self.net.on_change(listener)
+    }
+}
+
+impl Drop for Output {
+    fn drop(&mut self) {
+        self.net.detach(self.pin);
+    }
 }
 
 impl embedded_hal::digital::ErrorType for Output {
@@ -16,14 +51,22 @@ impl embedded_hal::digital::ErrorType for Output {
 
 impl embedded_hal::digital::OutputPin for Output {
     fn set_low(&mut self) -> Result<(), Self::Error> {
-        self.state.store(false, Ordering::SeqCst);
-        self.w.signal(PinState::Low);
+        self.set(PinState::Low);
         Ok(())
     }
 
     fn set_high(&mut self) -> Result<(), Self::Error> {
-        self.state.store(true, Ordering::SeqCst);
-        self.w.signal(PinState::High);
+        self.set(PinState::High);
         Ok(())
     }
 }
+
+impl embedded_hal::digital::StatefulOutputPin for Output {
+    fn is_set_high(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.state == PinState::High)
+    }
+
+    fn is_set_low(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.state == PinState::Low)
+    }
+}
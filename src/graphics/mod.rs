@@ -0,0 +1,466 @@
+use parking_lot::Mutex;
+use std::{
+    future::poll_fn,
+    iter,
+    ops::Range,
+    sync::{Arc, Weak},
+    task::{Poll, Waker},
+};
+
+use embedded_graphics::{
+    Pixel,
+    pixelcolor::{Gray8, PixelColor, Rgb888},
+    prelude::{Dimensions, DrawTarget, OriginDimensions, Point, RgbColor, Size},
+    primitives::{PointsIter, Rectangle},
+};
+
+mod panel;
+mod snapshot;
+pub mod ssd1306;
+pub mod st7789;
+
+pub use panel::{Orientation, Panel};
+pub use snapshot::Snapshot;
+
+/// A display with a framebuffer of `COLS` x `ROWS` pixels of color `C`
+///
+/// Any color of embedded-graphics can be used, e.g. `BinaryColor` for monochrome oleds
+/// or `Rgb565` for tfts. Use [to_rgba] to turn the shared framebuffer into something
+/// a ui can show.
+///
+/// Like the memory of a display controller, the shared framebuffer is written while
+/// drawing, there is no front buffer. Firmware calls [Display::flush] once a frame is
+/// complete, which tells every [FrameSubscriber] what changed. Viewers read the same
+/// framebuffer though, so when the firmware is already drawing the next frame they can
+/// show part of it, i.e. tearing, just like a real panel.
+///
+/// Backlight, power, inversion and orientation are kept as a [Panel], which viewers
+/// get from their [FrameSubscriber].
+pub struct Display<const COLS: usize, const ROWS: usize, C = Gray8> {
+    framebuffer: Arc<Mutex<[[C; COLS]; ROWS]>>,
+    /// Area drawn since the last flush
+    dirty: Option<Rectangle>,
+    subscribers: Vec<Weak<Mutex<Presented>>>,
+    panel: Arc<Mutex<Panel>>,
+}
+
+/// Frames presented that a subscriber has not taken yet
+#[derive(Default)]
+struct Presented {
+    dirty: Option<Rectangle>,
+    waker: Option<Waker>,
+}
+
+/// Gets notified about the frames flushed by a [Display]
+pub struct FrameSubscriber {
+    presented: Arc<Mutex<Presented>>,
+    panel: Arc<Mutex<Panel>>,
+}
+
+impl<const COLS: usize, const ROWS: usize, C: PixelColor + Default> Display<COLS, ROWS, C> {
+    pub fn new() -> (Self, Arc<Mutex<[[C; COLS]; ROWS]>>) {
+        let framebuffer = Arc::new(Mutex::new([[C::default(); _]; _]));
+        (
+            Self {
+                framebuffer: Arc::clone(&framebuffer),
+                dirty: None,
+                subscribers: Vec::new(),
+                panel: Arc::default(),
+            },
+            framebuffer,
+        )
+    }
+}
+
+impl<const COLS: usize, const ROWS: usize, C> Display<COLS, ROWS, C> {
+    /// Tell the viewers that a frame is complete
+    pub fn flush(&mut self) {
+        if let Some(dirty) = self.dirty.take() {
+            self.present(dirty);
+        }
+    }
+
+    /// Get notified about every flushed frame
+    pub fn subscribe(&mut self) -> FrameSubscriber {
+        let presented = Arc::default();
+        self.subscribers.push(Arc::downgrade(&presented));
+        FrameSubscriber {
+            presented,
+            panel: Arc::clone(&self.panel),
+        }
+    }
+
+    pub fn panel(&self) -> Panel {
+        *self.panel.lock()
+    }
+
+    /// Set the brightness of the backlight, from 0 (dark) to 255
+    pub fn set_backlight(&mut self, level: u8) {
+        self.update_panel(|panel| panel.backlight = level);
+    }
+
+    /// Turn the panel on or off, e.g. for sleep mode
+    pub fn set_on(&mut self, on: bool) {
+        self.update_panel(|panel| panel.on = on);
+    }
+
+    pub fn set_inverted(&mut self, inverted: bool) {
+        self.update_panel(|panel| panel.inverted = inverted);
+    }
+
+    /// Rotate what is drawn from now on, the framebuffer is left as it is
+    pub fn set_orientation(&mut self, orientation: Orientation) {
+        self.panel.lock().orientation = orientation;
+    }
+
+    /// Changes of the panel state show right away, without a flush
+    fn update_panel(&mut self, update: impl FnOnce(&mut Panel)) {
+        let mut panel = self.panel.lock();
+        let before = *panel;
+        update(&mut panel);
+        let changed = *panel != before;
+        drop(panel);
+        if changed {
+            self.present(panel_area(COLS, ROWS));
+        }
+    }
+
+    fn present(&mut self, dirty: Rectangle) {
+        self.subscribers.retain(|subscriber| {
+            let Some(presented) = subscriber.upgrade() else {
+                return false;
+            };
+            let mut presented = presented.lock();
+            presented.dirty = Some(union(presented.dirty, dirty));
+            if let Some(waker) = presented.waker.take() {
+                waker.wake();
+            }
+            true
+        });
+    }
+
+    fn mark_dirty(&mut self, area: Rectangle) {
+        self.dirty = Some(union(self.dirty, area));
+    }
+
+    /// Columns and rows of the framebuffer covered by `area`, none if it is off screen
+    fn visible(&self, area: &Rectangle) -> Option<(Range<usize>, Range<usize>)> {
+        let visible = area.intersection(&panel_area(COLS, ROWS));
+        let bottom_right = visible.bottom_right()?;
+        Some((
+            visible.top_left.x as usize..bottom_right.x as usize + 1,
+            visible.top_left.y as usize..bottom_right.y as usize + 1,
+        ))
+    }
+}
+
+impl<const COLS: usize, const ROWS: usize, C: Into<Rgb888> + Copy> Display<COLS, ROWS, C> {
+    /// Copy of what the panel shows right now, see [Panel::apply]
+    pub fn snapshot(&self) -> Snapshot {
+        let panel = self.panel();
+        Snapshot::capture(&self.framebuffer.lock()).map(|color| panel.apply(color))
+    }
+}
+
+impl FrameSubscriber {
+    /// The area changed by the frames flushed since the last call, none if there were none
+    pub fn take_dirty(&mut self) -> Option<Rectangle> {
+        self.presented.lock().dirty.take()
+    }
+
+    /// Backlight, power, inversion and orientation of the display
+    ///
+    /// Changes of them count as a frame covering the whole display.
+    pub fn panel(&self) -> Panel {
+        *self.panel.lock()
+    }
+
+    /// Wait for the next flushed frame and return the changed area
+    ///
+    /// Frames flushed since the last call count as well, so none are missed.
+    pub async fn wait(&mut self) -> Rectangle {
+        poll_fn(|cx| {
+            let mut presented = self.presented.lock();
+            match presented.dirty.take() {
+                Some(dirty) => Poll::Ready(dirty),
+                None => {
+                    presented.waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+}
+
+/// The whole framebuffer
+fn panel_area(cols: usize, rows: usize) -> Rectangle {
+    Rectangle::new(Point::zero(), Size::new(cols as u32, rows as u32))
+}
+
+/// Smallest rectangle containing both areas
+fn union(a: Option<Rectangle>, b: Rectangle) -> Rectangle {
+    let Some(a) = a else {
+        return b;
+    };
+    let (Some(a_end), Some(b_end)) = (a.bottom_right(), b.bottom_right()) else {
+        return if a.is_zero_sized() { b } else { a };
+    };
+    Rectangle::with_corners(
+        a.top_left.component_min(b.top_left),
+        a_end.component_max(b_end),
+    )
+}
+
+/// Convert pixels to 8 bit rgba, e.g. for `egui::ColorImage::from_rgba_unmultiplied`
+///
+/// ```
+/// # use embedded_graphics::pixelcolor::Rgb565;
+/// # use embedded_hal_sim::graphics::{self, Display};
+/// let (_display, framebuffer) = Display::<128, 64, Rgb565>::new();
+/// let rgba = graphics::to_rgba(framebuffer.lock().as_flattened());
+/// assert_eq!(rgba.len(), 128 * 64 * 4);
+/// ```
+pub fn to_rgba<C: Into<Rgb888> + Copy>(pixels: &[C]) -> Vec<u8> {
+    pixels
+        .iter()
+        .flat_map(|&color| {
+            let color: Rgb888 = color.into();
+            [color.r(), color.g(), color.b(), u8::MAX]
+        })
+        .collect()
+}
+
+impl<const COLS: usize, const ROWS: usize, C: PixelColor> DrawTarget for Display<COLS, ROWS, C> {
+    type Color = C;
+    // `ExampleDisplay` uses a framebuffer and doesn't need to communicate with the display
+    // controller to draw pixel, which means that drawing operations can never fail. To reflect
+    // this the type `Infallible` was chosen as the `Error` type.
+    type Error = core::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let orientation = self.panel.lock().orientation;
+        let bounds = self.bounding_box();
+        let mut framebuffer = self.framebuffer.lock();
+        let mut drawn: Option<(Point, Point)> = None;
+        for Pixel(coord, color) in pixels.into_iter() {
+            // The `DrawTarget` implementation is required to discard any out of bounds
+            // pixels without returning an error or causing a panic.
+            if !bounds.contains(coord) {
+                continue;
+            }
+            let coord = orientation.to_panel(coord, panel_area(COLS, ROWS).size);
+            framebuffer[coord.y as usize][coord.x as usize] = color;
+            drawn = Some(match drawn {
+                Some((min, max)) => (min.component_min(coord), max.component_max(coord)),
+                None => (coord, coord),
+            });
+        }
+        drop(framebuffer);
+
+        if let Some((min, max)) = drawn {
+            self.mark_dirty(Rectangle::with_corners(min, max));
+        }
+        Ok(())
+    }
+
+    fn fill_contiguous<I>(&mut self, area: &Rectangle, colors: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Self::Color>,
+    {
+        if self.panel().orientation != Orientation::Deg0 {
+            let pixels = area.points().zip(colors).map(|(p, c)| Pixel(p, c));
+            return self.draw_iter(pixels);
+        }
+        let Some((x, y)) = self.visible(area) else {
+            return Ok(());
+        };
+        let width = area.size.width as usize;
+        // Colors left and right of the visible part of each row are skipped
+        let left = (x.start as i32 - area.top_left.x) as usize;
+        let right = width - left - x.len();
+        let above = (y.start as i32 - area.top_left.y) as usize;
+
+        let mut colors = colors.into_iter();
+        if above > 0 {
+            colors.nth(above * width - 1);
+        }
+        self.mark_dirty(area.intersection(&panel_area(COLS, ROWS)));
+        let mut framebuffer = self.framebuffer.lock();
+        for row in &mut framebuffer[y] {
+            if left > 0 {
+                colors.nth(left - 1);
+            }
+            for (p, color) in row[x.clone()].iter_mut().zip(colors.by_ref()) {
+                *p = color;
+            }
+            if right > 0 {
+                colors.nth(right - 1);
+            }
+        }
+        Ok(())
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        if self.panel().orientation != Orientation::Deg0 {
+            return self.fill_contiguous(area, iter::repeat(color));
+        }
+        if let Some((x, y)) = self.visible(area) {
+            self.mark_dirty(area.intersection(&panel_area(COLS, ROWS)));
+            let mut framebuffer = self.framebuffer.lock();
+            for row in &mut framebuffer[y] {
+                row[x.clone()].fill(color);
+            }
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.framebuffer.lock().as_flattened_mut().fill(color);
+        self.mark_dirty(panel_area(COLS, ROWS));
+        Ok(())
+    }
+}
+
+impl<const COLS: usize, const ROWS: usize, C> OriginDimensions for Display<COLS, ROWS, C> {
+    /// Size the firmware draws on, which depends on the orientation
+    fn size(&self) -> Size {
+        let orientation = self.panel.lock().orientation;
+        orientation.size(panel_area(COLS, ROWS).size)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Display, Orientation, to_rgba};
+    use crate::time::VirtualClock;
+    use embedded_graphics::{
+        Pixel,
+        pixelcolor::{BinaryColor, Gray8, Rgb565},
+        prelude::{Dimensions, DrawTarget, GrayColor, OriginDimensions, Point, RgbColor, Size},
+        primitives::Rectangle,
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn test() {
+        let (mut display, framebuffer) = Display::<4, 2, Rgb565>::new();
+        let pixels = [
+            Pixel(Point::new(1, 0), Rgb565::RED),
+            Pixel(Point::new(3, 1), Rgb565::WHITE),
+            Pixel(Point::new(4, 0), Rgb565::GREEN),
+        ];
+        display.draw_iter(pixels).unwrap();
+        let rgba = to_rgba(framebuffer.lock().as_flattened());
+        assert_eq!(rgba[4..8], [255, 0, 0, 255]);
+        assert_eq!(rgba[28..], [255, 255, 255, 255]);
+        // The pixel out of bounds is discarded
+        let lit = rgba
+            .chunks(4)
+            .filter(|pixel| pixel[..3] != [0, 0, 0])
+            .count();
+        assert_eq!(lit, 2);
+
+        let (mut display, framebuffer) = Display::<2, 1, BinaryColor>::new();
+        display.clear(BinaryColor::On).unwrap();
+        assert_eq!(to_rgba(framebuffer.lock().as_flattened()), [255; 8]);
+    }
+
+    #[test]
+    fn fill() {
+        let (mut display, framebuffer) = Display::<3, 2>::new();
+        let gray = Gray8::new;
+
+        // Colors of pixels off screen are skipped
+        let area = Rectangle::new(Point::new(-1, -1), Size::new(3, 3));
+        display.fill_contiguous(&area, (0..9).map(gray)).unwrap();
+        assert_eq!(
+            *framebuffer.lock(),
+            [[4, 5, 0], [7, 8, 0]].map(|row| row.map(gray))
+        );
+
+        let area = Rectangle::new(Point::new(1, 1), Size::new(5, 5));
+        display.fill_solid(&area, gray(1)).unwrap();
+        assert_eq!(
+            *framebuffer.lock(),
+            [[4, 5, 0], [7, 1, 1]].map(|row| row.map(gray))
+        );
+
+        let area = Rectangle::new(Point::new(3, 0), Size::new(1, 1));
+        display.fill_solid(&area, gray(2)).unwrap();
+        display.clear(gray(3)).unwrap();
+        assert_eq!(*framebuffer.lock(), [[gray(3); 3]; 2]);
+    }
+
+    #[test]
+    fn flush() {
+        let (mut display, _framebuffer) = Display::<8, 8>::new();
+        let mut viewer = display.subscribe();
+
+        let pixels = [Point::new(1, 2), Point::new(3, 1), Point::new(9, 9)];
+        display
+            .draw_iter(pixels.map(|p| Pixel(p, Gray8::WHITE)))
+            .unwrap();
+        // Nothing is presented before the firmware flushes
+        assert_eq!(viewer.take_dirty(), None);
+        display.flush();
+        display
+            .fill_solid(
+                &Rectangle::new(Point::new(6, 6), Size::new(4, 4)),
+                Gray8::WHITE,
+            )
+            .unwrap();
+        display.flush();
+        assert_eq!(
+            viewer.take_dirty(),
+            Some(Rectangle::with_corners(Point::new(1, 1), Point::new(7, 7)))
+        );
+        assert_eq!(viewer.take_dirty(), None);
+
+        VirtualClock::new().block_on(async {
+            let flush = async {
+                crate::sleep(Duration::from_millis(1)).await;
+                display.clear(Gray8::BLACK).unwrap();
+                display.flush();
+            };
+            let (dirty, ()) = futures::join!(viewer.wait(), flush);
+            assert_eq!(dirty, display.bounding_box());
+        });
+    }
+
+    #[test]
+    fn panel() {
+        let (mut display, framebuffer) = Display::<3, 2>::new();
+        let mut viewer = display.subscribe();
+
+        display.set_orientation(Orientation::Deg90);
+        assert_eq!(display.size(), Size::new(2, 3));
+        display
+            .draw_iter([Pixel(Point::new(0, 0), Gray8::WHITE)])
+            .unwrap();
+        let area = Rectangle::new(Point::new(0, 2), Size::new(2, 1));
+        display.fill_solid(&area, Gray8::new(7)).unwrap();
+        let gray = Gray8::new;
+        assert_eq!(
+            *framebuffer.lock(),
+            [[7, 0, 255], [7, 0, 0]].map(|row| row.map(gray))
+        );
+
+        // Backlight, power and inversion are presented right away
+        display.set_backlight(128);
+        let whole = Rectangle::new(Point::zero(), Size::new(3, 2));
+        assert_eq!(viewer.take_dirty(), Some(whole));
+        assert_eq!(viewer.panel().apply(Gray8::WHITE), Gray8::new(128).into());
+        display.set_inverted(true);
+        assert_eq!(display.snapshot().pixel(1, 0), Some(Gray8::new(128).into()));
+        display.set_on(false);
+        assert_eq!(display.snapshot().pixel(1, 0), Some(Gray8::BLACK.into()));
+        assert_eq!(viewer.take_dirty(), Some(whole));
+        // Nothing changes
+        display.set_on(false);
+        assert_eq!(viewer.take_dirty(), None);
+    }
+}
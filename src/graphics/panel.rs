@@ -0,0 +1,82 @@
+use embedded_graphics::{
+    pixelcolor::Rgb888,
+    prelude::{Point, RgbColor, Size},
+};
+
+/// State of a display besides its pixels, set by the firmware and applied by viewers
+///
+/// The framebuffer always holds the pixels as the panel shows them, with the firmware
+/// drawing in its [Orientation]. Backlight, power and inversion are not applied to the
+/// framebuffer, viewers use [Panel::apply] or [Panel::to_rgba] for that.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Panel {
+    /// Brightness of the backlight, from 0 (dark) to 255
+    pub backlight: u8,
+    /// The panel shows nothing while off, e.g. in sleep mode
+    pub on: bool,
+    pub inverted: bool,
+    pub orientation: Orientation,
+}
+
+/// Clockwise rotation of what the firmware draws
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Orientation {
+    #[default]
+    Deg0,
+    Deg90,
+    Deg180,
+    Deg270,
+}
+
+impl Default for Panel {
+    /// Full backlight, on, not inverted and not rotated
+    fn default() -> Self {
+        Self {
+            backlight: u8::MAX,
+            on: true,
+            inverted: false,
+            orientation: Orientation::Deg0,
+        }
+    }
+}
+
+impl Panel {
+    /// What a viewer sees of a pixel
+    pub fn apply(&self, color: impl Into<Rgb888>) -> Rgb888 {
+        if !self.on {
+            return Rgb888::BLACK;
+        }
+        let color = color.into();
+        let [r, g, b] = [color.r(), color.g(), color.b()].map(|channel| {
+            let channel = if self.inverted { !channel } else { channel };
+            (u16::from(channel) * u16::from(self.backlight) / 255) as u8
+        });
+        Rgb888::new(r, g, b)
+    }
+
+    /// Like [to_rgba](super::to_rgba), with the panel state applied
+    pub fn to_rgba<C: Into<Rgb888> + Copy>(&self, pixels: &[C]) -> Vec<u8> {
+        super::to_rgba(&pixels.iter().map(|&c| self.apply(c)).collect::<Vec<_>>())
+    }
+}
+
+impl Orientation {
+    /// Size the firmware draws on for a panel of `size`
+    pub fn size(self, size: Size) -> Size {
+        match self {
+            Orientation::Deg0 | Orientation::Deg180 => size,
+            Orientation::Deg90 | Orientation::Deg270 => Size::new(size.height, size.width),
+        }
+    }
+
+    /// Where `point` drawn by the firmware ends up on a panel of `size`
+    pub fn to_panel(self, point: Point, size: Size) -> Point {
+        let (right, bottom) = (size.width as i32 - 1, size.height as i32 - 1);
+        match self {
+            Orientation::Deg0 => point,
+            Orientation::Deg90 => Point::new(right - point.y, point.x),
+            Orientation::Deg180 => Point::new(right - point.x, bottom - point.y),
+            Orientation::Deg270 => Point::new(point.y, bottom - point.x),
+        }
+    }
+}
@@ -0,0 +1,365 @@
+use embedded_graphics::{
+    pixelcolor::Rgb888,
+    prelude::{GrayColor, RgbColor},
+};
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+/// A copy of what a display shows, e.g. to compare it with a reference image
+///
+/// Images are saved and loaded as binary pgm or ppm, and as png with the `png` feature,
+/// depending on the extension of the file. Pgm only keeps the brightness, which is
+/// all there is for monochrome and grayscale displays.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    width: usize,
+    height: usize,
+    pixels: Vec<Rgb888>,
+}
+
+impl Snapshot {
+    /// Copy a framebuffer, e.g. `Snapshot::capture(&framebuffer.lock())`
+    pub fn capture<C: Into<Rgb888> + Copy, const COLS: usize, const ROWS: usize>(
+        framebuffer: &[[C; COLS]; ROWS],
+    ) -> Self {
+        Self {
+            width: COLS,
+            height: ROWS,
+            pixels: framebuffer
+                .as_flattened()
+                .iter()
+                .map(|&c| c.into())
+                .collect(),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Option<Rgb888> {
+        if x >= self.width {
+            return None;
+        }
+        self.pixels.get(y * self.width + x).copied()
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        match extension(path).as_str() {
+            "pgm" | "ppm" => parse_pnm(&fs::read(path)?),
+            #[cfg(feature = "png")]
+            "png" => load_png(path),
+            _ => Err(unsupported(path)),
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let (magic, bytes) = match extension(path).as_str() {
+            "pgm" => (
+                "P5",
+                self.pixels.iter().map(|&c| luma(c)).collect::<Vec<_>>(),
+            ),
+            "ppm" => ("P6", self.rgb()),
+            #[cfg(feature = "png")]
+            "png" => return self.save_png(path),
+            _ => return Err(unsupported(path)),
+        };
+        let mut file = io::BufWriter::new(fs::File::create(path)?);
+        write!(file, "{magic}\n{} {}\n255\n", self.width, self.height)?;
+        file.write_all(&bytes)?;
+        file.flush()
+    }
+
+    /// Compare with `reference`, and return an image showing the differences if they
+    /// don't match
+    ///
+    /// Pixels match if no color channel differs by more than `tolerance`. Pixels where
+    /// `mask` is not black are not compared, e.g. for a clock that is always different.
+    ///
+    /// In the returned image pixels that differ are red, the others are a dimmed copy
+    /// of `reference`.
+    pub fn diff(
+        &self,
+        reference: &Snapshot,
+        tolerance: u8,
+        mask: Option<&Snapshot>,
+    ) -> Option<Self> {
+        let width = self.width.max(reference.width);
+        let height = self.height.max(reference.height);
+        let mut matches = true;
+        let pixels = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let masked = mask
+                    .and_then(|mask| mask.pixel(x, y))
+                    .is_some_and(|c| c != Rgb888::BLACK);
+                let (actual, expected) = (self.pixel(x, y), reference.pixel(x, y));
+                let same = match (actual, expected) {
+                    (Some(a), Some(e)) => {
+                        let channels = [(a.r(), e.r()), (a.g(), e.g()), (a.b(), e.b())];
+                        channels.iter().all(|(a, e)| a.abs_diff(*e) <= tolerance)
+                    }
+                    _ => false,
+                };
+                matches &= same || masked;
+                match (same || masked, expected) {
+                    (true, Some(expected)) => {
+                        let dimmed = luma(expected) / 4;
+                        Rgb888::new(dimmed, dimmed, dimmed)
+                    }
+                    _ => Rgb888::RED,
+                }
+            })
+            .collect();
+        (!matches).then_some(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    /// Panic unless the snapshot matches the image at `reference`, see [Snapshot::diff]
+    ///
+    /// On a mismatch the snapshot and the differences are saved next to the reference,
+    /// with `.actual` and `.diff` added to the name. If the environment variable
+    /// `UPDATE_SNAPSHOTS` is set, the reference is written instead.
+    #[track_caller]
+    pub fn assert_matches(
+        &self,
+        reference: impl AsRef<Path>,
+        tolerance: u8,
+        mask: Option<&Snapshot>,
+    ) {
+        let reference = reference.as_ref();
+        if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+            self.save(reference).unwrap();
+            return;
+        }
+        let expected = Snapshot::load(reference)
+            .unwrap_or_else(|e| panic!("Failed to load {}: {e}", reference.display()));
+        let Some(diff) = self.diff(&expected, tolerance, mask) else {
+            return;
+        };
+        let (actual_path, diff_path) = (beside(reference, "actual"), beside(reference, "diff"));
+        self.save(&actual_path).unwrap();
+        diff.save(&diff_path).unwrap();
+        panic!(
+            "Display does not match {}, see {} and {}",
+            reference.display(),
+            actual_path.display(),
+            diff_path.display()
+        );
+    }
+
+    pub(super) fn map(mut self, f: impl Fn(Rgb888) -> Rgb888) -> Self {
+        self.pixels.iter_mut().for_each(|c| *c = f(*c));
+        self
+    }
+
+    fn rgb(&self) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flat_map(|c| [c.r(), c.g(), c.b()])
+            .collect()
+    }
+
+    #[cfg(feature = "png")]
+    fn save_png(&self, path: &Path) -> io::Result<()> {
+        let file = io::BufWriter::new(fs::File::create(path)?);
+        let mut encoder = png::Encoder::new(file, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.rgb())?;
+        Ok(writer.finish()?)
+    }
+}
+
+fn luma(color: Rgb888) -> u8 {
+    embedded_graphics::pixelcolor::Gray8::from(color).luma()
+}
+
+fn extension(path: &Path) -> String {
+    let extension = path.extension().unwrap_or_default();
+    extension.to_string_lossy().to_ascii_lowercase()
+}
+
+fn unsupported(path: &Path) -> io::Error {
+    let message = format!("Unsupported image format {}", path.display());
+    io::Error::new(io::ErrorKind::Unsupported, message)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// `name.png` becomes `name.{suffix}.png`
+fn beside(path: &Path, suffix: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{stem}.{suffix}.{}", extension(path)))
+}
+
+/// Parse a binary pgm (P5) or ppm (P6) with 8 bit samples
+fn parse_pnm(data: &[u8]) -> io::Result<Snapshot> {
+    let mut rest = data;
+    let mut tokens = Vec::new();
+    while tokens.len() < 4 {
+        // Whitespace and comments up to the end of the line separate the header fields
+        match rest.first() {
+            Some(b'#') => {
+                let end = rest.iter().position(|&b| b == b'\n').unwrap_or(rest.len());
+                rest = &rest[end..];
+            }
+            Some(b) if b.is_ascii_whitespace() => rest = &rest[1..],
+            _ => {
+                let end = rest
+                    .iter()
+                    .position(|b| b.is_ascii_whitespace())
+                    .ok_or_else(|| invalid("Truncated header"))?;
+                tokens.push(&rest[..end]);
+                rest = &rest[end..];
+            }
+        }
+    }
+    let channels = match tokens[0] {
+        b"P5" => 1,
+        b"P6" => 3,
+        _ => return Err(invalid("Not a binary pgm or ppm")),
+    };
+    let number = |token: &[u8]| {
+        let token = std::str::from_utf8(token).map_err(|_| invalid("Invalid header"))?;
+        token
+            .parse::<usize>()
+            .map_err(|_| invalid("Invalid header"))
+    };
+    let (width, height) = (number(tokens[1])?, number(tokens[2])?);
+    if number(tokens[3])? != 255 {
+        return Err(invalid("Only 8 bit samples are supported"));
+    }
+    let len = width
+        .checked_mul(height)
+        .and_then(|pixels| pixels.checked_mul(channels))
+        .ok_or_else(|| invalid("Image too large"))?;
+    // A single whitespace ends the header
+    let raster = rest
+        .get(1..)
+        .and_then(|raster| raster.get(..len))
+        .ok_or_else(|| invalid("Truncated image"))?;
+    let pixels = raster
+        .chunks(channels)
+        .map(|c| match *c {
+            [gray] => Rgb888::new(gray, gray, gray),
+            [r, g, b] => Rgb888::new(r, g, b),
+            _ => unreachable!(),
+        })
+        .collect();
+    Ok(Snapshot {
+        width,
+        height,
+        pixels,
+    })
+}
+
+#[cfg(feature = "png")]
+fn load_png(path: &Path) -> io::Result<Snapshot> {
+    let file = io::BufReader::new(fs::File::open(path)?);
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info()?;
+    let mut buf = vec![
+        0;
+        reader
+            .output_buffer_size()
+            .ok_or_else(|| invalid("Image too large"))?
+    ];
+    let info = reader.next_frame(&mut buf)?;
+    let samples = info.color_type.samples();
+    let pixels = buf[..info.buffer_size()]
+        .chunks(samples)
+        .map(|c| match *c {
+            [gray] | [gray, _] => Rgb888::new(gray, gray, gray),
+            [r, g, b] | [r, g, b, _] => Rgb888::new(r, g, b),
+            _ => unreachable!(),
+        })
+        .collect();
+    Ok(Snapshot {
+        width: info.width as usize,
+        height: info.height as usize,
+        pixels,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Snapshot, parse_pnm};
+    use embedded_graphics::{
+        pixelcolor::{Gray8, Rgb565, Rgb888},
+        prelude::{GrayColor, RgbColor},
+    };
+
+    #[test]
+    fn test() {
+        let dir = std::env::temp_dir().join(format!("snapshot-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut gray = [[Gray8::BLACK; 4]; 3];
+        gray[1][2] = Gray8::new(200);
+        let snapshot = Snapshot::capture(&gray);
+        snapshot.save(dir.join("gray.pgm")).unwrap();
+        assert_eq!(Snapshot::load(dir.join("gray.pgm")).unwrap(), snapshot);
+
+        let mut color = [[Rgb565::BLUE; 4]; 3];
+        color[0][0] = Rgb565::new(10, 20, 30);
+        let snapshot = Snapshot::capture(&color);
+        snapshot.save(dir.join("color.ppm")).unwrap();
+        let reference = Snapshot::load(dir.join("color.ppm")).unwrap();
+        assert_eq!(reference, snapshot);
+        snapshot.assert_matches(dir.join("color.ppm"), 0, None);
+
+        // Off by a little, masked, and really different
+        color[0][1] = Rgb565::new(0, 1, 31);
+        color[2][3] = Rgb565::RED;
+        let changed = Snapshot::capture(&color);
+        assert!(changed.diff(&reference, 4, None).is_some());
+        let mut mask = [[Rgb888::BLACK; 4]; 3];
+        mask[2][3] = Rgb888::WHITE;
+        assert_eq!(
+            changed.diff(&reference, 4, Some(&Snapshot::capture(&mask))),
+            None
+        );
+
+        let diff = changed.diff(&reference, 0, None).unwrap();
+        assert_eq!(diff.pixel(1, 0), Some(Rgb888::RED));
+        assert_eq!(diff.pixel(3, 2), Some(Rgb888::RED));
+        assert_ne!(diff.pixel(0, 0), Some(Rgb888::RED));
+
+        let result =
+            std::panic::catch_unwind(|| changed.assert_matches(dir.join("color.ppm"), 0, None));
+        assert!(result.is_err());
+        assert_eq!(
+            Snapshot::load(dir.join("color.actual.ppm")).unwrap(),
+            changed
+        );
+        assert_eq!(Snapshot::load(dir.join("color.diff.ppm")).unwrap(), diff);
+
+        let huge = format!("P6 {0} {0} 255\n", usize::MAX / 2);
+        assert!(parse_pnm(huge.as_bytes()).is_err());
+
+        #[cfg(feature = "png")]
+        {
+            changed.save(dir.join("color.png")).unwrap();
+            assert_eq!(Snapshot::load(dir.join("color.png")).unwrap(), changed);
+        }
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
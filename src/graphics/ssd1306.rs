@@ -0,0 +1,413 @@
+use embedded_graphics::{
+    pixelcolor::BinaryColor,
+    prelude::{DrawTarget, Point, Size},
+    primitives::{PointsIter, Rectangle},
+};
+use embedded_hal::digital::PinState;
+
+use super::{Display, panel_area, union};
+use crate::{
+    gpio::{Input, Observe},
+    i2c::{Direction, I2cDeviceModel},
+    spi::{IDLE_BYTE, SpiDeviceModel},
+};
+
+const RAM_COLS: usize = 128;
+const PAGES: usize = 8;
+
+/// Emulates the ssd1306 controller of monochrome oleds with `COLS` x `ROWS` pixels
+///
+/// Attach it to an [I2cBus](crate::i2c::I2cBus), or to spi with [Ssd1306::new_spi]
+/// for the 4-wire interface. The command stream of the firmware is interpreted like
+/// the controller does, and what changed is rendered into `display` at the end of every
+/// transaction.
+///
+/// Like on real modules the image is upside down unless the firmware sets the segment
+/// remap (0xA1) and the reverse com scan direction (0xC8). The contrast, inversion and
+/// display on/off commands set the [Panel](super::Panel) state instead of the pixels.
+///
+/// Horizontal scrolling is not rendered, only whether it is active is kept, see
+/// [Ssd1306::is_scrolling]. The controller moves the image by itself at a rate derived
+/// from its internal oscillator, which is not simulated.
+pub struct Ssd1306<const COLS: usize, const ROWS: usize> {
+    display: Display<COLS, ROWS, BinaryColor>,
+    /// Data/command select of the 4-wire spi interface, high for data
+    dc: Option<Input>,
+    ram: [[u8; RAM_COLS]; PAGES],
+    /// What the next i2c byte is
+    control: Control,
+    /// Command and parameters received so far
+    command: Vec<u8>,
+    addressing: Addressing,
+    columns: (u8, u8),
+    pages: (u8, u8),
+    column: u8,
+    page: u8,
+    entire_on: bool,
+    start_line: u8,
+    offset: u8,
+    segment_remap: bool,
+    com_reverse: bool,
+    scrolling: bool,
+    /// Area of the panel to render at the end of the transaction
+    dirty: Option<Rectangle>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Addressing {
+    Horizontal,
+    Vertical,
+    Page,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Control {
+    /// A control byte
+    Expected,
+    /// A single data or command byte followed by another control byte
+    Single { data: bool },
+    /// Data or command bytes until the end of the transaction
+    Stream { data: bool },
+}
+
+impl<const COLS: usize, const ROWS: usize> Ssd1306<COLS, ROWS> {
+    /// Controller on i2c, in the state after reset
+    pub fn new(mut display: Display<COLS, ROWS, BinaryColor>) -> Self {
+        assert!(COLS <= RAM_COLS && ROWS <= PAGES * 8);
+        display.set_backlight(0x7F);
+        display.set_on(false);
+        display.set_inverted(false);
+        Self {
+            display,
+            dc: None,
+            ram: [[0; _]; _],
+            control: Control::Expected,
+            command: Vec::new(),
+            addressing: Addressing::Page,
+            columns: (0, RAM_COLS as u8 - 1),
+            pages: (0, PAGES as u8 - 1),
+            column: 0,
+            page: 0,
+            entire_on: false,
+            start_line: 0,
+            offset: 0,
+            segment_remap: false,
+            com_reverse: false,
+            scrolling: false,
+            dirty: None,
+        }
+    }
+
+    /// Controller on spi, `dc` selects between commands (low) and data (high)
+    pub fn new_spi(display: Display<COLS, ROWS, BinaryColor>, dc: Input) -> Self {
+        Self {
+            dc: Some(dc),
+            ..Self::new(display)
+        }
+    }
+
+    /// The contrast, which is the brightness of the panel
+    pub fn contrast(&self) -> u8 {
+        self.display.panel().backlight
+    }
+
+    /// Whether the display is on, i.e. not in sleep mode
+    pub fn is_on(&self) -> bool {
+        self.display.panel().on
+    }
+
+    pub fn is_inverted(&self) -> bool {
+        self.display.panel().inverted
+    }
+
+    /// Whether horizontal scrolling is active, which is not rendered
+    pub fn is_scrolling(&self) -> bool {
+        self.scrolling
+    }
+
+    fn receive(&mut self, data: bool, byte: u8) {
+        if data {
+            self.write_ram(byte);
+            return;
+        }
+        self.command.push(byte);
+        if self.command.len() > parameters(self.command[0]) {
+            let command = std::mem::take(&mut self.command);
+            self.execute(&command);
+        }
+    }
+
+    fn write_ram(&mut self, byte: u8) {
+        self.ram[usize::from(self.page)][usize::from(self.column)] = byte;
+        self.mark(usize::from(self.page), usize::from(self.column));
+
+        let (columns, pages) = (self.columns, self.pages);
+        let next = |pointer: u8, (start, end): (u8, u8), count: usize| match pointer == end {
+            true => (start, true),
+            false => ((pointer + 1) % count as u8, false),
+        };
+        match self.addressing {
+            Addressing::Horizontal => {
+                let wrapped;
+                (self.column, wrapped) = next(self.column, columns, RAM_COLS);
+                if wrapped {
+                    self.page = next(self.page, pages, PAGES).0;
+                }
+            }
+            Addressing::Vertical => {
+                let wrapped;
+                (self.page, wrapped) = next(self.page, pages, PAGES);
+                if wrapped {
+                    self.column = next(self.column, columns, RAM_COLS).0;
+                }
+            }
+            Addressing::Page => self.column = next(self.column, columns, RAM_COLS).0,
+        }
+    }
+
+    fn execute(&mut self, command: &[u8]) {
+        match *command {
+            [0x81, contrast] => self.display.set_backlight(contrast),
+            [0xA4] => self.entire_on = false,
+            [0xA5] => self.entire_on = true,
+            [0xA6] => self.display.set_inverted(false),
+            [0xA7] => self.display.set_inverted(true),
+            [0xAE] => self.display.set_on(false),
+            [0xAF] => self.display.set_on(true),
+            [0x20, mode] => {
+                self.addressing = match mode & 0x03 {
+                    0 => Addressing::Horizontal,
+                    1 => Addressing::Vertical,
+                    _ => Addressing::Page,
+                }
+            }
+            [0x21, start, end] => {
+                self.columns = (start & 0x7F, end & 0x7F);
+                self.column = self.columns.0;
+            }
+            [0x22, start, end] => {
+                self.pages = (start & 0x07, end & 0x07);
+                self.page = self.pages.0;
+            }
+            [low @ 0x00..=0x0F] => self.column = self.column & 0x70 | low,
+            [high @ 0x10..=0x17] => self.column = (high & 0x07) << 4 | self.column & 0x0F,
+            [page @ 0xB0..=0xB7] => self.page = page & 0x07,
+            [line @ 0x40..=0x7F] => self.start_line = line & 0x3F,
+            [0xD3, offset] => self.offset = offset & 0x3F,
+            [0xA0] => self.segment_remap = false,
+            [0xA1] => self.segment_remap = true,
+            [0xC0] => self.com_reverse = false,
+            [0xC8] => self.com_reverse = true,
+            [0x2E] => self.scrolling = false,
+            [0x2F] => self.scrolling = true,
+            // Scroll setup, timing and charge pump settings have no visible effect
+            [0x26 | 0x27, ..] | [0x29 | 0x2A, ..] | [0xA3, ..] => {}
+            [0xA8 | 0xD5 | 0xD9 | 0xDA | 0xDB | 0x8D, _] | [0xE3] => {}
+            _ => log::warn!("Unknown ssd1306 command {command:02X?}"),
+        }
+        // Commands that move or replace the whole image
+        if matches!(
+            *command,
+            [0xA0 | 0xA1 | 0xA4 | 0xA5 | 0xC0 | 0xC8] | [0x40..=0x7F] | [0xD3, _]
+        ) {
+            self.dirty = Some(panel_area(COLS, ROWS));
+        }
+    }
+
+    /// Render the pixels of memory `page`, `column` at the end of the transaction
+    fn mark(&mut self, page: usize, column: usize) {
+        let x = if self.segment_remap {
+            column
+        } else {
+            RAM_COLS - 1 - column
+        };
+        if x >= COLS || self.entire_on {
+            return;
+        }
+        let lines = PAGES * 8;
+        let shift = usize::from(self.start_line + self.offset) % lines;
+        for line in page * 8..page * 8 + 8 {
+            let com = (line + lines - shift) % lines;
+            if com >= ROWS {
+                continue;
+            }
+            let y = if self.com_reverse {
+                com
+            } else {
+                ROWS - 1 - com
+            };
+            let pixel = Rectangle::new(Point::new(x as i32, y as i32), Size::new(1, 1));
+            self.dirty = Some(union(self.dirty, pixel));
+        }
+    }
+
+    fn pixel(&self, x: usize, y: usize) -> BinaryColor {
+        if self.entire_on {
+            return BinaryColor::On;
+        }
+        // Without the remap, segment 0 shows memory column 127
+        let column = if self.segment_remap {
+            x
+        } else {
+            RAM_COLS - 1 - x
+        };
+        let com = if self.com_reverse { y } else { ROWS - 1 - y };
+        let line = (com + usize::from(self.start_line + self.offset)) % (PAGES * 8);
+        let lit = self.ram[line / 8][column] >> (line % 8) & 1 == 1;
+        BinaryColor::from(lit)
+    }
+
+    /// Show what changed since the last transaction
+    fn render(&mut self) {
+        let Some(area) = self.dirty.take() else {
+            return;
+        };
+        let pixels: Vec<_> = area
+            .points()
+            .map(|point| self.pixel(point.x as usize, point.y as usize))
+            .collect();
+        let Ok(()) = self.display.fill_contiguous(&area, pixels);
+        self.display.flush();
+    }
+}
+
+/// Number of parameter bytes following a command
+fn parameters(command: u8) -> usize {
+    match command {
+        0x81 | 0x20 | 0xA8 | 0xD3 | 0xD5 | 0xD9 | 0xDA | 0xDB | 0x8D => 1,
+        0x21 | 0x22 | 0xA3 => 2,
+        0x29 | 0x2A => 5,
+        0x26 | 0x27 => 6,
+        _ => 0,
+    }
+}
+
+impl<const COLS: usize, const ROWS: usize> I2cDeviceModel for Ssd1306<COLS, ROWS> {
+    fn start(&mut self, direction: Direction) -> bool {
+        self.control = Control::Expected;
+        // Reading the status is not supported
+        direction == Direction::Write
+    }
+
+    fn write(&mut self, byte: u8) -> bool {
+        match self.control {
+            Control::Expected => {
+                let data = byte & 0x40 != 0;
+                self.control = match byte & 0x80 != 0 {
+                    true => Control::Single { data },
+                    false => Control::Stream { data },
+                };
+            }
+            Control::Single { data } => {
+                self.receive(data, byte);
+                self.control = Control::Expected;
+            }
+            Control::Stream { data } => self.receive(data, byte),
+        }
+        true
+    }
+
+    fn read(&mut self) -> u8 {
+        0xFF
+    }
+
+    fn stop(&mut self) {
+        self.render();
+    }
+}
+
+impl<const COLS: usize, const ROWS: usize> SpiDeviceModel for Ssd1306<COLS, ROWS> {
+    fn deselect(&mut self) {
+        self.render();
+    }
+
+    fn transfer(&mut self, mosi: u8) -> u8 {
+        let data = self
+            .dc
+            .as_ref()
+            .is_some_and(|dc| dc.level() == PinState::High);
+        self.receive(data, mosi);
+        IDLE_BYTE
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Ssd1306;
+    use crate::{graphics::Display, i2c::I2cBus};
+    use embedded_graphics::{
+        pixelcolor::BinaryColor,
+        prelude::{Point, Size},
+        primitives::Rectangle,
+    };
+    use embedded_hal::i2c::I2c;
+
+    #[test]
+    fn test() {
+        let (mut display, framebuffer) = Display::<128, 64, BinaryColor>::new();
+        let mut frames = display.subscribe();
+        let mut bus = I2cBus::new();
+        let oled = bus.attach(0x3C, Ssd1306::new(display));
+
+        // Horizontal addressing over the whole panel, upright
+        let init = [
+            0x00, 0xAE, 0x20, 0x00, 0x21, 0, 127, 0x22, 0, 7, 0xA1, 0xC8, 0xAF,
+        ];
+        bus.write(0x3Cu8, &init).unwrap();
+        assert!(oled.lock().is_on());
+        assert!(
+            framebuffer
+                .lock()
+                .as_flattened()
+                .iter()
+                .all(|&c| c.is_off())
+        );
+
+        // A diagonal in the top left corner and a pixel in the top right one, only the
+        // first page is rendered again
+        frames.take_dirty();
+        let mut data = vec![0x40, 0x01, 0x02, 0x04, 0x08];
+        data.extend([0; 123]);
+        data.push(0x80);
+        bus.write(0x3Cu8, &data).unwrap();
+        assert_eq!(
+            frames.take_dirty(),
+            Some(Rectangle::new(Point::zero(), Size::new(128, 8)))
+        );
+        {
+            let framebuffer = framebuffer.lock();
+            for i in 0..4 {
+                assert!(framebuffer[i][i].is_on());
+            }
+            assert!(framebuffer[7][127].is_on());
+            assert_eq!(
+                framebuffer
+                    .as_flattened()
+                    .iter()
+                    .filter(|c| c.is_on())
+                    .count(),
+                5
+            );
+        }
+
+        // Single command bytes with continuation, contrast and inversion
+        bus.write(0x3Cu8, &[0x80, 0x81, 0x80, 0x10, 0x80, 0xA7])
+            .unwrap();
+        assert_eq!(oled.lock().contrast(), 0x10);
+        assert_eq!(frames.panel().backlight, 0x10);
+        assert!(frames.panel().inverted);
+        assert!(framebuffer.lock()[0][0].is_on());
+
+        // Page addressing of the second page with the start line moved up by 8 rows
+        bus.write(0x3Cu8, &[0x00, 0xA6, 0x20, 0x02, 0xB1, 0x02, 0x10, 0x48])
+            .unwrap();
+        bus.write(0x3Cu8, &[0x40, 0xFF]).unwrap();
+        assert_eq!(framebuffer.lock()[0][1], BinaryColor::Off);
+        assert!((0..8).all(|y| framebuffer.lock()[y][2].is_on()));
+
+        // The panel is dark while the display sleeps
+        bus.write(0x3Cu8, &[0x00, 0xAE]).unwrap();
+        assert!(!frames.panel().on);
+        assert!(!oled.lock().is_on());
+    }
+}
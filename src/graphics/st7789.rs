@@ -0,0 +1,390 @@
+use embedded_graphics::{
+    pixelcolor::{Rgb565, raw::RawU16},
+    prelude::{DrawTarget, Point, RgbColor, Size},
+    primitives::{PointsIter, Rectangle},
+};
+use embedded_hal::digital::PinState;
+
+use super::{Display, panel_area, union};
+use crate::{
+    gpio::{Input, Observe},
+    spi::{IDLE_BYTE, SpiDeviceModel},
+};
+
+const RAM_COLS: usize = 240;
+const RAM_ROWS: usize = 320;
+
+/// Emulates the st7789 controller of color tfts with `COLS` x `ROWS` pixels
+///
+/// Attach it to spi, `dc` selects between commands (low) and their parameters or pixel
+/// data (high). The command stream of the firmware is interpreted like the controller
+/// does, and what changed is rendered into `display` at the end of every transaction.
+/// Sleep, display on/off, inversion and brightness (0x51) set the
+/// [Panel](super::Panel) state instead of the pixels.
+///
+/// The controller has memory for 240 x 320 pixels. Panels with less pixels only show
+/// part of it, see [St7789::set_offset].
+pub struct St7789<const COLS: usize, const ROWS: usize> {
+    display: Display<COLS, ROWS, Rgb565>,
+    dc: Input,
+    ram: Vec<Rgb565>,
+    /// Memory column and row of the top left pixel of the panel
+    offset: (usize, usize),
+    /// Last command received
+    command: u8,
+    /// Parameters or pixel data received for the command
+    parameters: Vec<u8>,
+    registers: Registers,
+    /// Area of the panel to render at the end of the transaction
+    dirty: Option<Rectangle>,
+}
+
+/// State that is lost on a software reset
+struct Registers {
+    columns: (u16, u16),
+    rows: (u16, u16),
+    /// Memory column and row written next, before [Registers::madctl] is applied
+    pointer: (u16, u16),
+    madctl: u8,
+    colmod: u8,
+    sleeping: bool,
+    on: bool,
+    inverted: bool,
+    /// Top fixed area and vertical scrolling area
+    scroll_area: (u16, u16),
+    scroll_start: u16,
+}
+
+impl Default for Registers {
+    fn default() -> Self {
+        Self {
+            columns: (0, RAM_COLS as u16 - 1),
+            rows: (0, RAM_ROWS as u16 - 1),
+            pointer: (0, 0),
+            madctl: 0,
+            colmod: 0x66,
+            sleeping: true,
+            on: false,
+            inverted: false,
+            scroll_area: (0, RAM_ROWS as u16),
+            scroll_start: 0,
+        }
+    }
+}
+
+const MADCTL_MY: u8 = 0x80;
+const MADCTL_MX: u8 = 0x40;
+const MADCTL_MV: u8 = 0x20;
+const MADCTL_BGR: u8 = 0x08;
+
+impl<const COLS: usize, const ROWS: usize> St7789<COLS, ROWS> {
+    /// Controller in the state after reset
+    pub fn new(mut display: Display<COLS, ROWS, Rgb565>, dc: Input) -> Self {
+        assert!(COLS <= RAM_COLS && ROWS <= RAM_ROWS);
+        display.set_on(false);
+        display.set_inverted(false);
+        Self {
+            display,
+            dc,
+            ram: vec![Rgb565::BLACK; RAM_COLS * RAM_ROWS],
+            offset: (0, 0),
+            command: 0,
+            parameters: Vec::new(),
+            registers: Registers::default(),
+            dirty: None,
+        }
+    }
+
+    /// Place the panel at `column`, `row` of the memory, e.g. 52, 40 for 135 x 240 panels
+    pub fn set_offset(&mut self, column: usize, row: usize) {
+        assert!(column + COLS <= RAM_COLS && row + ROWS <= RAM_ROWS);
+        self.offset = (column, row);
+        self.mark_all();
+        self.render();
+    }
+
+    /// Whether the display is on and out of sleep mode
+    pub fn is_on(&self) -> bool {
+        self.registers.on && !self.registers.sleeping
+    }
+
+    pub fn is_inverted(&self) -> bool {
+        self.registers.inverted
+    }
+
+    fn execute(&mut self, command: u8) {
+        self.command = command;
+        self.parameters.clear();
+        let registers = &mut self.registers;
+        match command {
+            0x01 => {
+                *registers = Registers::default();
+                self.dirty = Some(panel_area(COLS, ROWS));
+            }
+            0x10 => registers.sleeping = true,
+            0x11 => registers.sleeping = false,
+            0x20 => registers.inverted = false,
+            0x21 => registers.inverted = true,
+            0x28 => registers.on = false,
+            0x29 => registers.on = true,
+            0x2C => registers.pointer = (registers.columns.0, registers.rows.0),
+            // Handled when the parameters or pixels arrive
+            0x2A | 0x2B | 0x33 | 0x36 | 0x37 | 0x3A | 0x3C | 0x51 => {}
+            // Modes, tearing, porch, power and gamma settings have no visible effect
+            0x00 | 0x12 | 0x13 | 0x26 | 0x34 | 0x35 | 0x38 | 0x39 | 0x53 | 0x55 => {}
+            0xB0..=0xEF => {}
+            _ => log::warn!("Unknown st7789 command {command:02X}"),
+        }
+        self.display.set_on(self.is_on());
+        self.display.set_inverted(self.registers.inverted);
+    }
+
+    fn receive(&mut self, byte: u8) {
+        self.parameters.push(byte);
+        let registers = &mut self.registers;
+        match (self.command, &self.parameters[..]) {
+            (0x2A, &[s0, s1, e0, e1]) => {
+                registers.columns = (u16::from_be_bytes([s0, s1]), u16::from_be_bytes([e0, e1]))
+            }
+            (0x2B, &[s0, s1, e0, e1]) => {
+                registers.rows = (u16::from_be_bytes([s0, s1]), u16::from_be_bytes([e0, e1]))
+            }
+            (0x33, &[t0, t1, v0, v1, _, _]) => {
+                registers.scroll_area =
+                    (u16::from_be_bytes([t0, t1]), u16::from_be_bytes([v0, v1]));
+                self.mark_all();
+            }
+            (0x37, &[s0, s1]) => {
+                registers.scroll_start = u16::from_be_bytes([s0, s1]);
+                self.mark_all();
+            }
+            // The color order applies to the whole memory
+            (0x36, &[madctl]) => {
+                registers.madctl = madctl;
+                self.mark_all();
+            }
+            (0x51, &[brightness]) => self.display.set_backlight(brightness),
+            (0x3A, &[colmod]) => {
+                if !matches!(colmod & 0x07, 0x03 | 0x05 | 0x06) {
+                    log::warn!("Unsupported st7789 color format {colmod:02X}, using 18 bit");
+                }
+                registers.colmod = colmod;
+            }
+            (0x2C | 0x3C, &[high, low]) if registers.colmod & 0x07 == 0x05 => {
+                let color = Rgb565::from(RawU16::new(u16::from_be_bytes([high, low])));
+                self.write_pixel(color);
+            }
+            // 12 bit colors pack two pixels into three bytes
+            (0x2C | 0x3C, &[rg, br, gb]) if registers.colmod & 0x07 == 0x03 => {
+                self.write_pixel(rgb444(rg >> 4, rg & 0x0F, br >> 4));
+                self.write_pixel(rgb444(br & 0x0F, gb >> 4, gb & 0x0F));
+            }
+            // 18 bit colors use the upper 6 bits of every byte
+            (0x2C | 0x3C, &[r, g, b]) => {
+                let color = Rgb565::new(r >> 3, g >> 2, b >> 3);
+                self.write_pixel(color);
+            }
+            _ => {}
+        }
+    }
+
+    fn write_pixel(&mut self, color: Rgb565) {
+        self.parameters.clear();
+        let (column, row) = self.registers.pointer;
+        if let Some(index) = memory_index(self.registers.madctl, column, row) {
+            self.ram[index] = color;
+            self.mark(index);
+        }
+
+        let registers = &mut self.registers;
+        registers.pointer = match (column >= registers.columns.1, row >= registers.rows.1) {
+            (false, _) => (column + 1, row),
+            (true, false) => (registers.columns.0, row + 1),
+            (true, true) => (registers.columns.0, registers.rows.0),
+        };
+    }
+
+    /// Render the memory at `index` at the end of the transaction
+    fn mark(&mut self, index: usize) {
+        let registers = &self.registers;
+        let (top, area) = (
+            usize::from(registers.scroll_area.0),
+            usize::from(registers.scroll_area.1),
+        );
+        let start = usize::from(registers.scroll_start).saturating_sub(top);
+        if top + area <= RAM_ROWS && area > 0 && start % area != 0 {
+            // Scrolled memory is shown somewhere else
+            self.mark_all();
+            return;
+        }
+        let x = (index % RAM_COLS).checked_sub(self.offset.0);
+        let y = (index / RAM_COLS).checked_sub(self.offset.1);
+        if let (Some(x), Some(y)) = (x, y)
+            && x < COLS
+            && y < ROWS
+        {
+            let pixel = Rectangle::new(Point::new(x as i32, y as i32), Size::new(1, 1));
+            self.dirty = Some(union(self.dirty, pixel));
+        }
+    }
+
+    fn mark_all(&mut self) {
+        self.dirty = Some(panel_area(COLS, ROWS));
+    }
+
+    fn pixel(&self, x: usize, y: usize) -> Rgb565 {
+        let registers = &self.registers;
+        let (top, area) = (
+            usize::from(registers.scroll_area.0),
+            usize::from(registers.scroll_area.1),
+        );
+        let mut row = y + self.offset.1;
+        if top + area <= RAM_ROWS && (top..top + area).contains(&row) {
+            let start = usize::from(registers.scroll_start).saturating_sub(top);
+            row = top + (row - top + start) % area;
+        }
+
+        let color = self.ram[row * RAM_COLS + x + self.offset.0];
+        match registers.madctl & MADCTL_BGR != 0 {
+            true => Rgb565::new(color.b(), color.g(), color.r()),
+            false => color,
+        }
+    }
+
+    /// Show what changed since the last transaction
+    fn render(&mut self) {
+        let Some(area) = self.dirty.take() else {
+            return;
+        };
+        let pixels: Vec<_> = area
+            .points()
+            .map(|point| self.pixel(point.x as usize, point.y as usize))
+            .collect();
+        let Ok(()) = self.display.fill_contiguous(&area, pixels);
+        self.display.flush();
+    }
+}
+
+fn rgb444(r: u8, g: u8, b: u8) -> Rgb565 {
+    Rgb565::new(r << 1 | r >> 3, g << 2 | g >> 2, b << 1 | b >> 3)
+}
+
+/// Where the pixel written at `column`, `row` ends up in memory, none if outside of it
+///
+/// Rows and columns are exchanged first, then mirrored.
+fn memory_index(madctl: u8, column: u16, row: u16) -> Option<usize> {
+    let (mut x, mut y) = match madctl & MADCTL_MV != 0 {
+        true => (usize::from(row), usize::from(column)),
+        false => (usize::from(column), usize::from(row)),
+    };
+    if x >= RAM_COLS || y >= RAM_ROWS {
+        return None;
+    }
+    if madctl & MADCTL_MX != 0 {
+        x = RAM_COLS - 1 - x;
+    }
+    if madctl & MADCTL_MY != 0 {
+        y = RAM_ROWS - 1 - y;
+    }
+    Some(y * RAM_COLS + x)
+}
+
+impl<const COLS: usize, const ROWS: usize> SpiDeviceModel for St7789<COLS, ROWS> {
+    fn deselect(&mut self) {
+        self.render();
+    }
+
+    fn transfer(&mut self, mosi: u8) -> u8 {
+        match self.dc.level() {
+            PinState::Low => self.execute(mosi),
+            PinState::High => self.receive(mosi),
+        }
+        IDLE_BYTE
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::St7789;
+    use crate::{gpio, gpio::Output, graphics::Display, spi::SpiDevice};
+    use embedded_graphics::{
+        pixelcolor::Rgb565,
+        prelude::{Point, RgbColor, Size},
+        primitives::Rectangle,
+    };
+    use embedded_hal::{
+        digital::{OutputPin, PinState},
+        spi::SpiDevice as _,
+    };
+
+    #[test]
+    fn test() {
+        let (mut display, framebuffer) = Display::<240, 240, Rgb565>::new();
+        let mut frames = display.subscribe();
+        let (dc_sense, mut dc) = gpio::new(PinState::Low);
+        let (_cs_sense, cs) = gpio::new(PinState::High);
+        let (mut spi, tft) = SpiDevice::new(cs, St7789::new(display, dc_sense));
+        let mut send = |command: u8, parameters: &[u8]| {
+            let set_dc = |dc: &mut Output, state| dc.set_state(state).unwrap();
+            set_dc(&mut dc, PinState::Low);
+            spi.write(&[command]).unwrap();
+            set_dc(&mut dc, PinState::High);
+            spi.write(parameters).unwrap();
+        };
+
+        send(0x01, &[]);
+        send(0x11, &[]);
+        send(0x3A, &[0x55]);
+        send(0x29, &[]);
+        assert!(tft.lock().is_on());
+
+        // A red square of 2 x 2 pixels, only it is rendered again
+        frames.take_dirty();
+        send(0x2A, &[0, 10, 0, 11]);
+        send(0x2B, &[0, 20, 0, 21]);
+        send(0x2C, &[0xF8, 0x00].repeat(4));
+        assert_eq!(
+            frames.take_dirty(),
+            Some(Rectangle::new(Point::new(10, 20), Size::new(2, 2)))
+        );
+        let pixel = |x: usize, y: usize| framebuffer.lock()[y][x];
+        assert_eq!(pixel(10, 20), Rgb565::RED);
+        assert_eq!(pixel(11, 21), Rgb565::RED);
+        assert_eq!(pixel(12, 20), Rgb565::BLACK);
+        assert_eq!(pixel(10, 22), Rgb565::BLACK);
+
+        // Scrolled up by 20 rows
+        send(0x33, &[0, 0, 0x01, 0x40, 0, 0]);
+        send(0x37, &[0, 20]);
+        assert_eq!(pixel(10, 0), Rgb565::RED);
+        send(0x37, &[0, 0]);
+
+        // Mirrored columns, in 18 bit color
+        send(0x36, &[0x40]);
+        send(0x3A, &[0x66]);
+        send(0x2A, &[0, 0, 0, 0]);
+        send(0x2B, &[0, 0, 0, 0]);
+        send(0x2C, &[0x00, 0xFC, 0x00]);
+        assert_eq!(pixel(239, 0), Rgb565::GREEN);
+
+        // Two pixels in 12 bit color
+        send(0x3A, &[0x53]);
+        send(0x2A, &[0, 0, 0, 1]);
+        send(0x2B, &[0, 1, 0, 1]);
+        send(0x2C, &[0xF0, 0x0F, 0xFF]);
+        assert_eq!(pixel(239, 1), Rgb565::RED);
+        assert_eq!(pixel(238, 1), Rgb565::WHITE);
+
+        // Inversion, brightness and sleep are up to the panel
+        send(0x21, &[]);
+        send(0x51, &[0x80]);
+        assert!(frames.panel().inverted);
+        assert_eq!(frames.panel().backlight, 0x80);
+        assert_eq!(pixel(10, 20), Rgb565::RED);
+
+        send(0x10, &[]);
+        assert!(!tft.lock().is_on());
+        assert!(!frames.panel().on);
+        assert_eq!(pixel(10, 20), Rgb565::RED);
+    }
+}
@@ -0,0 +1,243 @@
+use embedded_hal::i2c::{
+    ErrorKind, ErrorType, NoAcknowledgeSource, Operation, SevenBitAddress, TenBitAddress,
+};
+use parking_lot::Mutex;
+use std::{collections::HashMap, sync::Arc};
+
+/// Direction of the transfer following a start condition
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Read,
+    Write,
+}
+
+/// A simulated device on an i2c bus
+///
+/// This is implemented by the stimulus side to answer the transactions done by the
+/// application code.
+pub trait I2cDeviceModel {
+    /// The device was addressed by a start or repeated start condition
+    ///
+    /// Return `false` to not acknowledge the address, e.g. while busy.
+    fn start(&mut self, _direction: Direction) -> bool {
+        true
+    }
+
+    /// A byte was written by the application, return `false` to not acknowledge it
+    fn write(&mut self, byte: u8) -> bool;
+
+    /// The application reads a byte
+    fn read(&mut self) -> u8;
+
+    /// A stop condition ended the transaction
+    fn stop(&mut self) {}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Address {
+    SevenBit(SevenBitAddress),
+    TenBit(TenBitAddress),
+}
+
+type SharedModel = Arc<Mutex<dyn I2cDeviceModel + Send>>;
+
+/// A simulated i2c bus with devices attached at 7-bit or 10-bit addresses
+///
+/// Transactions to an address without any device fail with
+/// [ErrorKind::NoAcknowledge], just like on a real bus.
+#[derive(Default)]
+pub struct I2cBus {
+    devices: HashMap<Address, SharedModel>,
+}
+
+impl I2cBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Attach `model` at the 7-bit `address`
+    ///
+    /// The returned handle can be used by the stimulus side to inspect or modify the model
+    pub fn attach<M: I2cDeviceModel + Send + 'static>(
+        &mut self,
+        address: SevenBitAddress,
+        model: M,
+    ) -> Arc<Mutex<M>> {
+        assert!(address < 0x80, "{address:#x} is not a 7-bit address");
+        self.attach_at(Address::SevenBit(address), model)
+    }
+
+    /// Attach `model` at the 10-bit `address`
+    pub fn attach_ten_bit<M: I2cDeviceModel + Send + 'static>(
+        &mut self,
+        address: TenBitAddress,
+        model: M,
+    ) -> Arc<Mutex<M>> {
+        assert!(address < 0x400, "{address:#x} is not a 10-bit address");
+        self.attach_at(Address::TenBit(address), model)
+    }
+
+    fn attach_at<M: I2cDeviceModel + Send + 'static>(
+        &mut self,
+        address: Address,
+        model: M,
+    ) -> Arc<Mutex<M>> {
+        let model = Arc::new(Mutex::new(model));
+        let previous = self
+            .devices
+            .insert(address, Arc::clone(&model) as SharedModel);
+        assert!(previous.is_none(), "{address:?} is already in use");
+        model
+    }
+
+    fn transaction(
+        &mut self,
+        address: Address,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), ErrorKind> {
+        let Some(model) = self.devices.get(&address) else {
+            return Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address));
+        };
+        let mut model = model.lock();
+
+        let mut result = Ok(());
+        let mut direction = None;
+        for operation in operations {
+            let this_direction = match operation {
+                Operation::Read(_) => Direction::Read,
+                Operation::Write(_) => Direction::Write,
+            };
+
+            // Adjacent operations of the same kind are not separated by a repeated start
+            if direction != Some(this_direction) {
+                direction = Some(this_direction);
+                if !model.start(this_direction) {
+                    result = Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address));
+                    break;
+                }
+            }
+
+            match operation {
+                Operation::Read(buf) => buf.iter_mut().for_each(|b| *b = model.read()),
+                Operation::Write(buf) => {
+                    if !buf.iter().all(|b| model.write(*b)) {
+                        result = Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Data));
+                        break;
+                    }
+                }
+            }
+        }
+        model.stop();
+        result
+    }
+}
+
+impl ErrorType for I2cBus {
+    type Error = ErrorKind;
+}
+
+impl embedded_hal::i2c::I2c<SevenBitAddress> for I2cBus {
+    fn transaction(
+        &mut self,
+        address: SevenBitAddress,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        I2cBus::transaction(self, Address::SevenBit(address), operations)
+    }
+}
+
+impl embedded_hal::i2c::I2c<TenBitAddress> for I2cBus {
+    fn transaction(
+        &mut self,
+        address: TenBitAddress,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        I2cBus::transaction(self, Address::TenBit(address), operations)
+    }
+}
+
+impl embedded_hal_async::i2c::I2c<SevenBitAddress> for I2cBus {
+    async fn transaction(
+        &mut self,
+        address: SevenBitAddress,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        I2cBus::transaction(self, Address::SevenBit(address), operations)
+    }
+}
+
+impl embedded_hal_async::i2c::I2c<TenBitAddress> for I2cBus {
+    async fn transaction(
+        &mut self,
+        address: TenBitAddress,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        I2cBus::transaction(self, Address::TenBit(address), operations)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Direction, I2cBus, I2cDeviceModel};
+    use embedded_hal::i2c::{ErrorKind, I2c, NoAcknowledgeSource};
+
+    /// Register file where the first written byte selects the register
+    #[derive(Default)]
+    struct Registers {
+        registers: [u8; 4],
+        pointer: usize,
+        address_phase: bool,
+    }
+
+    impl I2cDeviceModel for Registers {
+        fn start(&mut self, direction: Direction) -> bool {
+            self.address_phase = direction == Direction::Write;
+            true
+        }
+
+        fn write(&mut self, byte: u8) -> bool {
+            if core::mem::take(&mut self.address_phase) {
+                self.pointer = byte as usize;
+                return self.pointer < self.registers.len();
+            }
+            let Some(register) = self.registers.get_mut(self.pointer) else {
+                return false;
+            };
+            *register = byte;
+            self.pointer += 1;
+            true
+        }
+
+        fn read(&mut self) -> u8 {
+            let byte = self.registers[self.pointer % self.registers.len()];
+            self.pointer += 1;
+            byte
+        }
+    }
+
+    #[test]
+    fn test() {
+        let mut bus = I2cBus::new();
+        let seven_bit = bus.attach(0x50, Registers::default());
+        let ten_bit = bus.attach_ten_bit(0x350, Registers::default());
+
+        bus.write(0x50u8, &[1, 0xAB, 0xCD]).unwrap();
+        assert_eq!(seven_bit.lock().registers, [0, 0xAB, 0xCD, 0]);
+
+        let mut buf = [0; 2];
+        bus.write_read(0x50u8, &[1], &mut buf).unwrap();
+        assert_eq!(buf, [0xAB, 0xCD]);
+
+        bus.write(0x350u16, &[3, 0x12]).unwrap();
+        assert_eq!(ten_bit.lock().registers, [0, 0, 0, 0x12]);
+
+        assert_eq!(
+            bus.write(0x51u8, &[0]),
+            Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address))
+        );
+        assert_eq!(
+            bus.write(0x50u8, &[3, 1, 2]),
+            Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Data))
+        );
+    }
+}
@@ -1,14 +1,18 @@
 pub mod adc;
+pub mod delay;
 #[cfg(feature = "ethernet")]
 pub mod ethernet;
 #[cfg(feature = "flash")]
 pub mod flash;
 pub mod gpio;
 pub mod graphics;
+pub mod i2c;
+pub mod pwm;
 pub mod serial;
+pub mod spi;
+pub mod time;
+pub mod touch;
 pub mod utils;
+pub mod vcd;
 
-#[cfg(target_arch = "wasm32")]
-pub use gloo_timers::future::sleep;
-#[cfg(feature = "tokio")]
-pub use tokio::time::{Interval, sleep};
+pub use time::{Interval, interval, sleep};
@@ -0,0 +1,136 @@
+use embedded_hal::pwm::{ErrorType, SetDutyCycle};
+use std::sync::{
+    Arc,
+    atomic::{AtomicU16, AtomicU32, Ordering},
+};
+
+use crate::utils::{self, SignalRx, SignalTx};
+
+/// A simulated pwm channel
+///
+/// The duty cycle and frequency set on this channel can be read back
+/// from the corresponding [PwmChannelStimulus]
+pub struct PwmChannel {
+    state: Arc<PwmState>,
+    w: SignalTx<()>,
+}
+
+#[derive(Clone)]
+pub struct PwmChannelStimulus {
+    state: Arc<PwmState>,
+    w: SignalRx<()>,
+}
+
+struct PwmState {
+    duty_cycle: AtomicU16,
+    max_duty_cycle: u16,
+    frequency: AtomicU32,
+}
+
+impl PwmChannel {
+    /// Create a channel with a duty cycle range of `0..=max_duty_cycle` running at
+    /// `frequency` Hz, the duty cycle starts out at 0
+    pub fn new(max_duty_cycle: u16, frequency: u32) -> (Self, PwmChannelStimulus) {
+        let state = Arc::new(PwmState {
+            duty_cycle: AtomicU16::new(0),
+            max_duty_cycle,
+            frequency: AtomicU32::new(frequency),
+        });
+        let (tx, rx) = utils::signal(());
+        (
+            Self {
+                state: Arc::clone(&state),
+                w: tx,
+            },
+            PwmChannelStimulus { state, w: rx },
+        )
+    }
+
+    /// Set the pwm frequency in Hz
+    pub fn set_frequency(&mut self, frequency: u32) {
+        self.state.frequency.store(frequency, Ordering::SeqCst);
+        self.w.signal(());
+    }
+
+    /// The pwm frequency in Hz
+    pub fn frequency(&self) -> u32 {
+        self.state.frequency.load(Ordering::SeqCst)
+    }
+}
+
+impl ErrorType for PwmChannel {
+    type Error = core::convert::Infallible;
+}
+
+impl SetDutyCycle for PwmChannel {
+    fn max_duty_cycle(&self) -> u16 {
+        self.state.max_duty_cycle
+    }
+
+    fn set_duty_cycle(&mut self, duty: u16) -> Result<(), Self::Error> {
+        self.state
+            .duty_cycle
+            .store(duty.min(self.state.max_duty_cycle), Ordering::SeqCst);
+        self.w.signal(());
+        Ok(())
+    }
+}
+
+impl PwmChannelStimulus {
+    pub fn duty_cycle(&self) -> u16 {
+        self.state.duty_cycle.load(Ordering::SeqCst)
+    }
+
+    pub fn max_duty_cycle(&self) -> u16 {
+        self.state.max_duty_cycle
+    }
+
+    /// The duty cycle as a fraction in `0.0..=1.0`
+    pub fn duty_cycle_fraction(&self) -> f32 {
+        match self.max_duty_cycle() {
+            0 => 0.0,
+            max => f32::from(self.duty_cycle()) / f32::from(max),
+        }
+    }
+
+    /// The pwm frequency in Hz
+    pub fn frequency(&self) -> u32 {
+        self.state.frequency.load(Ordering::SeqCst)
+    }
+
+    /// Wait until the duty cycle or frequency is set again
+    ///
+    /// Returns false once the [PwmChannel] is dropped and nothing changes anymore.
+    pub async fn wait_for_change(&mut self) -> bool {
+        self.w.wait().await.is_some()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PwmChannel;
+    use crate::time::VirtualClock;
+    use embedded_hal::pwm::SetDutyCycle;
+    use futures::FutureExt;
+    use std::time::Duration;
+
+    #[test]
+    fn test() {
+        let (mut pwm, mut stimulus) = PwmChannel::new(1000, 20_000);
+        let mut ui = stimulus.clone();
+
+        VirtualClock::new().block_on(async {
+            let set = async {
+                crate::sleep(Duration::from_millis(1)).await;
+                pwm.set_duty_cycle_percent(25).unwrap();
+            };
+            futures::join!(stimulus.wait_for_change(), ui.wait_for_change(), set);
+        });
+        assert_eq!(stimulus.duty_cycle(), 250);
+        assert_eq!(ui.duty_cycle_fraction(), 0.25);
+        assert_eq!(ui.frequency(), 20_000);
+
+        drop(pwm);
+        assert_eq!(stimulus.wait_for_change().now_or_never(), Some(false));
+    }
+}
@@ -0,0 +1,148 @@
+use embedded_hal::digital::{OutputPin, PinState};
+use parking_lot::Mutex;
+use std::{sync::Arc, time::Duration};
+
+use super::Error;
+use crate::{gpio::Output, time::Instant};
+
+/// Line parameters of one end of a uart
+///
+/// Both ends have to agree on them, bytes received with different parameters than
+/// they were sent with read as framing or parity errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    pub baudrate: u32,
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+}
+
+impl Default for Config {
+    /// 115200 baud 8N1
+    fn default() -> Self {
+        Self {
+            baudrate: 115_200,
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataBits {
+    Five = 5,
+    Six = 6,
+    Seven = 7,
+    Eight = 8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopBits {
+    One = 1,
+    Two = 2,
+}
+
+impl Config {
+    /// Time it takes to send a single character, including start, parity and stop bits
+    pub fn frame_time(&self) -> Duration {
+        let parity = u64::from(self.parity != Parity::None);
+        let bits = 1 + self.data_bits as u64 + parity + self.stop_bits as u64;
+        Duration::from_nanos(bits * 1_000_000_000 / u64::from(self.baudrate))
+    }
+
+    /// Only the lowest `data_bits` of `byte` are sent
+    pub(crate) fn mask(&self, byte: u8) -> u8 {
+        byte & (0xFF >> (8 - self.data_bits as u8))
+    }
+
+    /// Receivers tolerate a baud rate error of a few percent
+    fn baudrate_matches(&self, other: &Config) -> bool {
+        self.baudrate.abs_diff(other.baudrate) as u64 * 100 <= u64::from(self.baudrate) * 3
+    }
+}
+
+/// What is sent on the line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Symbol {
+    Data(u8),
+    ParityError,
+    FramingError,
+    Break,
+    /// Not on the line, the receiver lost bytes
+    Overrun,
+}
+
+/// A symbol together with when it is on the line
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Frame {
+    pub(crate) symbol: Symbol,
+    /// Configuration of the sender
+    pub(crate) config: Config,
+    pub(crate) start: Instant,
+    pub(crate) end: Instant,
+}
+
+impl Frame {
+    /// What a receiver configured with `config` reads
+    pub(crate) fn decode(&self, config: &Config) -> Result<u8, Error> {
+        match self.symbol {
+            Symbol::Data(_) | Symbol::ParityError | Symbol::FramingError
+                if !config.baudrate_matches(&self.config)
+                    || config.data_bits != self.config.data_bits =>
+            {
+                Err(Error::Framing)
+            }
+            Symbol::Data(_) if config.parity != self.config.parity => Err(Error::Parity),
+            Symbol::Data(byte) => Ok(byte),
+            Symbol::ParityError => Err(Error::Parity),
+            Symbol::FramingError => Err(Error::Framing),
+            Symbol::Break => Err(Error::Break),
+            Symbol::Overrun => Err(Error::Overrun),
+        }
+    }
+}
+
+/// Characters sent to one end that it has not read yet
+///
+/// Characters count from when they are written, not from when they arrive, since the
+/// channel between the ends holds both.
+#[derive(Default)]
+pub(crate) struct Fill {
+    count: usize,
+    /// Deasserted while at least `threshold` characters are waiting
+    rts: Option<(Output, usize)>,
+}
+
+pub(crate) type SharedFill = Arc<Mutex<Fill>>;
+
+impl Fill {
+    pub(crate) fn push(&mut self) {
+        self.count += 1;
+        self.update_rts();
+    }
+
+    pub(crate) fn pop(&mut self) {
+        self.count = self.count.saturating_sub(1);
+        self.update_rts();
+    }
+
+    pub(crate) fn set_rts(&mut self, rts: Output, threshold: usize) {
+        self.rts = Some((rts, threshold));
+        self.update_rts();
+    }
+
+    fn update_rts(&mut self) {
+        if let Some((rts, threshold)) = &mut self.rts {
+            // Active low, like the pins of a real uart
+            let Ok(()) = rts.set_state(PinState::from(self.count >= *threshold));
+        }
+    }
+}
@@ -0,0 +1,603 @@
+use std::{
+    future::poll_fn,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::{
+    StreamExt,
+    channel::mpsc::{self, TryRecvError},
+};
+
+use crate::{
+    gpio::{Input, Observe, Output},
+    time::{self, Instant},
+};
+use embedded_hal::digital::PinState;
+use embedded_hal_async::digital::Wait;
+
+mod line;
+#[cfg(feature = "pty")]
+pub mod pty;
+pub mod rs485;
+#[cfg(feature = "tcp")]
+pub mod tcp;
+
+pub use line::{Config, DataBits, Parity, StopBits};
+use line::{Frame, SharedFill, Symbol};
+
+/// A simulated uart
+///
+/// Both the application side and the stimulus side are [Uart]s, every byte written
+/// to one of them is read from the other. Besides the methods below, both sides
+/// implement the blocking and async [embedded_io] traits.
+///
+/// Each end has its own [Config]. Characters take as long to arrive as they would on a
+/// real line at the configured baud rate, measured on the simulator clock. Line errors
+/// sent with e.g. [Uart::inject_parity_error] or caused by mismatched configurations
+/// are returned as errors by the reads at the other end.
+///
+/// Hardware flow control is available through [Uart::set_rts] and [Uart::set_cts].
+pub struct Uart {
+    rx: mpsc::Receiver<Frame>,
+    tx: mpsc::Sender<Frame>,
+    timeout: Duration,
+    config: Config,
+    /// When the transmitter is done sending everything written so far
+    line_free: Instant,
+    /// Frame taken from `rx` that has not been read yet
+    peeked: Option<Frame>,
+    /// Characters waiting to be read by this end
+    fill: SharedFill,
+    /// Characters waiting to be read by the other end
+    peer_fill: SharedFill,
+    cts: Option<Input>,
+}
+
+pub type UartStimulus = Uart;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The other side of the uart has been dropped
+    Disconnected,
+    /// A character was received without a valid stop bit
+    Framing,
+    Parity,
+    /// Received characters were lost
+    Overrun,
+    /// The line was held low for longer than a character
+    Break,
+    /// There is no room in the buffer or the other end is not ready to receive, only
+    /// returned by [Uart::try_write]
+    Full,
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Error::Disconnected => write!(f, "the other side of the uart has been dropped"),
+            Error::Framing => write!(f, "framing error"),
+            Error::Parity => write!(f, "parity error"),
+            Error::Overrun => write!(f, "overrun"),
+            Error::Break => write!(f, "break condition"),
+            Error::Full => write!(f, "the uart buffer is full"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for std::io::Error {
+    fn from(e: Error) -> Self {
+        let kind = match e {
+            Error::Disconnected => std::io::ErrorKind::BrokenPipe,
+            Error::Framing | Error::Parity => std::io::ErrorKind::InvalidData,
+            Error::Overrun | Error::Break => std::io::ErrorKind::Other,
+            Error::Full => std::io::ErrorKind::WouldBlock,
+        };
+        std::io::Error::new(kind, e)
+    }
+}
+
+impl embedded_io::Error for Error {
+    fn kind(&self) -> embedded_io::ErrorKind {
+        match self {
+            Error::Disconnected => embedded_io::ErrorKind::BrokenPipe,
+            Error::Framing | Error::Parity => embedded_io::ErrorKind::InvalidData,
+            Error::Overrun | Error::Break | Error::Full => embedded_io::ErrorKind::Other,
+        }
+    }
+}
+
+impl Uart {
+    /// Create both ends of a uart using [Config::default]
+    pub fn new(timeout: Duration, buffer_size: usize) -> (Uart, UartStimulus) {
+        let (rx_sender, rx_receiver) = mpsc::channel(buffer_size);
+        let (tx_sender, tx_receiver) = mpsc::channel(buffer_size);
+        let (rx_fill, tx_fill) = (SharedFill::default(), SharedFill::default());
+
+        (
+            Uart::from_channels(
+                rx_receiver,
+                tx_sender,
+                rx_fill.clone(),
+                tx_fill.clone(),
+                timeout,
+            ),
+            UartStimulus::from_channels(tx_receiver, rx_sender, tx_fill, rx_fill, timeout),
+        )
+    }
+
+    fn from_channels(
+        rx: mpsc::Receiver<Frame>,
+        tx: mpsc::Sender<Frame>,
+        fill: SharedFill,
+        peer_fill: SharedFill,
+        timeout: Duration,
+    ) -> Self {
+        Self {
+            rx,
+            tx,
+            timeout,
+            config: Config::default(),
+            line_free: Instant::default(),
+            peeked: None,
+            fill,
+            peer_fill,
+            cts: None,
+        }
+    }
+
+    pub fn config(&self) -> Config {
+        self.config
+    }
+
+    /// Change the line parameters of this end, the other end is not affected
+    pub fn set_config(&mut self, config: Config) {
+        assert!(config.baudrate > 0, "baudrate must not be zero");
+        self.config = config;
+    }
+
+    /// Drive `rts` from the fill level of this end
+    ///
+    /// Like on a real uart the pin is active low. It is deasserted (high) while
+    /// `threshold` or more characters wait to be read, and asserted (low) otherwise.
+    /// Connect it to the cts of the other end to stop it from sending.
+    pub fn set_rts(&mut self, rts: Output, threshold: usize) {
+        self.fill.lock().set_rts(rts, threshold);
+    }
+
+    /// Only start sending characters while `cts` is asserted (low)
+    ///
+    /// Writes wait for the other end to become ready and [Uart::try_write] fails with
+    /// [Error::Full].
+    pub fn set_cts(&mut self, cts: Input) {
+        self.cts = Some(cts);
+    }
+
+    /// Read until `dst` is full or the line has been idle for the configured timeout
+    ///
+    /// The timeout is measured on the simulator clock, see [crate::time]. Like for
+    /// [Read::read](embedded_io_async::Read::read), a line error is only returned if it
+    /// is the first thing read.
+    pub async fn read_until_idle(&mut self, dst: &mut [u8]) -> Result<usize, Error> {
+        for (i, byte) in dst.iter_mut().enumerate() {
+            // The line is idle if no character starts within the timeout
+            match time::timeout(self.timeout, self.wait_for_start()).await {
+                Ok(started) => started?,
+                Err(_) => return Ok(i),
+            }
+            match self.read_byte(i == 0).await {
+                Ok(b) => *byte = b,
+                // Keep what has been read, the error is returned by the next read
+                Err(_) if i > 0 => return Ok(i),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(dst.len())
+    }
+
+    /// Write all of `src`, waiting for room in the buffer when needed
+    pub async fn write(&mut self, src: &[u8]) -> Result<(), Error> {
+        for byte in src {
+            self.send(
+                Symbol::Data(self.config.mask(*byte)),
+                self.config.frame_time(),
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Write all of `src` without waiting, fails with [Error::Full] if it does not fit
+    ///
+    /// Everything before the character that did not fit has been written.
+    pub fn try_write(&mut self, src: &[u8]) -> Result<(), Error> {
+        for byte in src {
+            self.try_send(
+                Symbol::Data(self.config.mask(*byte)),
+                self.config.frame_time(),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Send a character that the other end receives with a parity error
+    pub async fn inject_parity_error(&mut self) -> Result<(), Error> {
+        self.send(Symbol::ParityError, self.config.frame_time())
+            .await
+    }
+
+    /// Send a character that the other end receives without a valid stop bit
+    pub async fn inject_framing_error(&mut self) -> Result<(), Error> {
+        self.send(Symbol::FramingError, self.config.frame_time())
+            .await
+    }
+
+    /// Hold the line low for `duration`, but at least for one character
+    pub async fn send_break(&mut self, duration: Duration) -> Result<(), Error> {
+        self.send(Symbol::Break, duration.max(self.config.frame_time()))
+            .await
+    }
+
+    /// Make the other end report that it lost characters
+    ///
+    /// The characters sent so far are still received, the read after them fails with
+    /// [Error::Overrun].
+    pub async fn inject_overrun(&mut self) -> Result<(), Error> {
+        self.send(Symbol::Overrun, Duration::ZERO).await
+    }
+
+    /// Put `symbol` on the line once the transmitter is done with what was sent before
+    async fn send(&mut self, symbol: Symbol, duration: Duration) -> Result<(), Error> {
+        loop {
+            if let Some(cts) = &mut self.cts {
+                let Ok(()) = cts.wait_for_low().await;
+            }
+            poll_fn(|cx| self.tx.poll_ready(cx))
+                .await
+                .map_err(|_| Error::Disconnected)?;
+            match self.try_send(symbol, duration) {
+                // Cts was deasserted while waiting for room
+                Err(Error::Full) => continue,
+                result => return result,
+            }
+        }
+    }
+
+    fn try_send(&mut self, symbol: Symbol, duration: Duration) -> Result<(), Error> {
+        if let Some(cts) = &self.cts
+            && cts.level() == PinState::High
+        {
+            return Err(Error::Full);
+        }
+        let start = self.line_free.max(time::now());
+        let frame = Frame {
+            symbol,
+            config: self.config,
+            start,
+            end: start + duration,
+        };
+        self.tx
+            .try_send(frame)
+            .map_err(|e| match e.is_disconnected() {
+                true => Error::Disconnected,
+                false => Error::Full,
+            })?;
+        self.line_free = frame.end;
+        self.peer_fill.lock().push();
+        Ok(())
+    }
+
+    /// The next frame on the line, it is kept until [Uart::read_received] consumes it
+    async fn peek(&mut self) -> Result<Frame, Error> {
+        if let Some(frame) = self.peeked {
+            return Ok(frame);
+        }
+        let frame = self.rx.next().await.ok_or(Error::Disconnected)?;
+        self.peeked = Some(frame);
+        Ok(frame)
+    }
+
+    fn try_peek(&mut self) -> Result<Option<Frame>, Error> {
+        if self.peeked.is_none() {
+            self.peeked = match self.rx.try_recv() {
+                Ok(frame) => Some(frame),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Closed) => return Err(Error::Disconnected),
+            };
+        }
+        Ok(self.peeked)
+    }
+
+    async fn wait_for_start(&mut self) -> Result<(), Error> {
+        let frame = self.peek().await?;
+        sleep_until(frame.start).await;
+        Ok(())
+    }
+
+    /// Done with the peeked frame
+    fn consume(&mut self) {
+        self.peeked = None;
+        self.fill.lock().pop();
+    }
+
+    /// Read the next character, a line error is left for the next read unless `first`
+    async fn read_byte(&mut self, first: bool) -> Result<u8, Error> {
+        let frame = self.peek().await?;
+        sleep_until(frame.end).await;
+        let result = frame.decode(&self.config);
+        if result.is_ok() || first {
+            self.consume();
+        }
+        result
+    }
+
+    /// Wait for at least one character, then read whatever else has been received
+    async fn read_available(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let frame = self.peek().await?;
+        sleep_until(frame.end).await;
+        self.read_received(buf)
+    }
+
+    /// Read the characters that have been received completely by now
+    ///
+    /// A line error is only returned if it is the first thing read, otherwise it is
+    /// left for the next read.
+    fn read_received(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let now = time::now();
+        for (i, byte) in buf.iter_mut().enumerate() {
+            let Ok(Some(frame)) = self.try_peek() else {
+                return Ok(i);
+            };
+            if frame.end > now {
+                return Ok(i);
+            }
+            match frame.decode(&self.config) {
+                Ok(b) => *byte = b,
+                Err(_) if i > 0 => return Ok(i),
+                Err(e) => {
+                    self.consume();
+                    return Err(e);
+                }
+            }
+            self.consume();
+        }
+        Ok(buf.len())
+    }
+
+    /// Wait for room for at least one character, then write whatever else fits
+    async fn write_available(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        let Some((first, rest)) = buf.split_first() else {
+            return Ok(0);
+        };
+        let (config, frame_time) = (self.config, self.config.frame_time());
+        self.send(Symbol::Data(config.mask(*first)), frame_time)
+            .await?;
+
+        let written = rest
+            .iter()
+            .take_while(|byte| {
+                self.try_send(Symbol::Data(config.mask(**byte)), frame_time)
+                    .is_ok()
+            })
+            .count();
+        Ok(1 + written)
+    }
+}
+
+impl embedded_io::ErrorType for Uart {
+    type Error = Error;
+}
+
+impl embedded_io::Read for Uart {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let frame = futures::executor::block_on(self.peek())?;
+        crate::delay::block_for(frame.end - time::now());
+        self.read_received(buf)
+    }
+}
+
+impl embedded_io::ReadReady for Uart {
+    fn read_ready(&mut self) -> Result<bool, Self::Error> {
+        let now = time::now();
+        Ok(matches!(self.try_peek()?, Some(frame) if frame.end <= now))
+    }
+}
+
+impl embedded_io::Write for Uart {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        futures::executor::block_on(self.write_available(buf))
+    }
+
+    /// Wait until everything written has been sent
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        crate::delay::block_for(self.line_free - time::now());
+        Ok(())
+    }
+}
+
+impl embedded_io::WriteReady for Uart {
+    fn write_ready(&mut self) -> Result<bool, Self::Error> {
+        if let Some(cts) = &self.cts
+            && cts.level() == PinState::High
+        {
+            return Ok(false);
+        }
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        match self.tx.poll_ready(&mut cx) {
+            Poll::Ready(Ok(())) => Ok(true),
+            Poll::Ready(Err(_)) => Err(Error::Disconnected),
+            Poll::Pending => Ok(false),
+        }
+    }
+}
+
+impl embedded_io_async::Read for Uart {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.read_available(buf).await
+    }
+}
+
+impl embedded_io_async::Write for Uart {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.write_available(buf).await
+    }
+
+    /// Wait until everything written has been sent
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        sleep_until(self.line_free).await;
+        Ok(())
+    }
+}
+
+/// Wait for `deadline`, without a timer if it has passed already, e.g. for frames
+/// that arrived while the application was busy
+async fn sleep_until(deadline: Instant) {
+    let now = time::now();
+    if deadline > now {
+        time::sleep(deadline - now).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Config, Error, Parity, Uart};
+    use crate::{gpio, time::VirtualClock};
+    use embedded_hal::digital::PinState;
+    use embedded_io::{ReadReady, WriteReady};
+    use embedded_io_async::{Read, Write};
+    use std::time::Duration;
+
+    #[test]
+    fn test() {
+        let (mut uart, mut stimulus) = Uart::new(Duration::from_millis(10), 4);
+
+        VirtualClock::new().block_on(async {
+            Write::write_all(&mut stimulus, b"AT\r\n").await.unwrap();
+            Write::flush(&mut stimulus).await.unwrap();
+            let mut buf = [0; 8];
+            assert_eq!(Read::read(&mut uart, &mut buf).await, Ok(4));
+            assert_eq!(&buf[..4], b"AT\r\n");
+
+            // Only what fits in the buffer is written without waiting
+            let written = Write::write(&mut uart, b"OK\r\nOK\r\n").await.unwrap();
+            assert!(written < 8);
+            assert_eq!(stimulus.read_until_idle(&mut buf).await, Ok(written));
+
+            assert!(!stimulus.read_ready().unwrap());
+            embedded_io::Write::write_all(&mut uart, b"x").unwrap();
+            assert!(!stimulus.read_ready().unwrap());
+            embedded_io::Write::flush(&mut uart).unwrap();
+            assert!(stimulus.read_ready().unwrap());
+            let mut buf = [0; 2];
+            assert_eq!(embedded_io::Read::read(&mut stimulus, &mut buf), Ok(1));
+        });
+
+        drop(uart);
+        let mut buf = [0; 2];
+        assert_eq!(
+            embedded_io::Read::read(&mut stimulus, &mut buf),
+            Err(Error::Disconnected)
+        );
+    }
+
+    #[test]
+    fn line() {
+        let clock = VirtualClock::new();
+        let (mut uart, mut stimulus) = Uart::new(Duration::from_millis(10), 16);
+        let config = Config {
+            baudrate: 9600,
+            ..Config::default()
+        };
+        uart.set_config(config);
+        stimulus.set_config(config);
+
+        clock.block_on(async {
+            let start = crate::time::now();
+            stimulus.write(b"0123456789").await.unwrap();
+            let mut buf = [0; 10];
+            uart.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"0123456789");
+            assert_eq!(start.elapsed(), config.frame_time() * 10);
+
+            stimulus.write(b"a").await.unwrap();
+            stimulus.inject_parity_error().await.unwrap();
+            stimulus.write(b"b").await.unwrap();
+            stimulus.inject_framing_error().await.unwrap();
+            stimulus.send_break(Duration::from_millis(5)).await.unwrap();
+            stimulus.inject_overrun().await.unwrap();
+            stimulus.write(b"c").await.unwrap();
+            crate::sleep(Duration::from_millis(20)).await;
+
+            // Bytes before an error are returned first
+            assert_eq!(Read::read(&mut uart, &mut buf).await, Ok(1));
+            assert_eq!(Read::read(&mut uart, &mut buf).await, Err(Error::Parity));
+            assert_eq!(Read::read(&mut uart, &mut buf).await, Ok(1));
+            assert_eq!(Read::read(&mut uart, &mut buf).await, Err(Error::Framing));
+            assert_eq!(Read::read(&mut uart, &mut buf).await, Err(Error::Break));
+            assert_eq!(Read::read(&mut uart, &mut buf).await, Err(Error::Overrun));
+            assert_eq!(Read::read(&mut uart, &mut buf).await, Ok(1));
+            assert_eq!(buf[0], b'c');
+
+            stimulus.write(b"ef").await.unwrap();
+            stimulus.inject_framing_error().await.unwrap();
+            assert_eq!(uart.read_until_idle(&mut buf).await, Ok(2));
+            assert_eq!(&buf[..2], b"ef");
+            assert_eq!(uart.read_until_idle(&mut buf).await, Err(Error::Framing));
+
+            // Both ends have to agree on the line parameters
+            uart.set_config(Config {
+                parity: Parity::Even,
+                ..config
+            });
+            stimulus.write(b"d").await.unwrap();
+            assert_eq!(Read::read(&mut uart, &mut buf).await, Err(Error::Parity));
+            uart.set_config(Config {
+                baudrate: 19200,
+                ..config
+            });
+            stimulus.write(b"e").await.unwrap();
+            assert_eq!(Read::read(&mut uart, &mut buf).await, Err(Error::Framing));
+        });
+    }
+
+    #[test]
+    fn flow_control() {
+        let clock = VirtualClock::new();
+        let (mut uart, mut stimulus) = Uart::new(Duration::from_millis(10), 16);
+        let (stimulus_cts, uart_rts) = gpio::new(PinState::Low);
+        uart.set_rts(uart_rts, 4);
+        stimulus.set_cts(stimulus_cts);
+
+        clock.block_on(async {
+            // Sending stops once the receiver is full
+            assert_eq!(stimulus.try_write(b"0123456789"), Err(Error::Full));
+            assert!(!stimulus.write_ready().unwrap());
+            let mut buf = [0; 4];
+            uart.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"0123");
+            assert!(stimulus.write_ready().unwrap());
+            assert_eq!(stimulus.try_write(b"4"), Ok(()));
+
+            let write = async {
+                stimulus.write(b"56789").await.unwrap();
+                crate::time::now()
+            };
+            let read = async {
+                crate::sleep(Duration::from_millis(10)).await;
+                let mut buf = [0; 6];
+                uart.read_exact(&mut buf).await.unwrap();
+                assert_eq!(&buf, b"456789");
+            };
+            let (written, ()) = futures::join!(write, read);
+            assert!(written.since_start() >= Duration::from_millis(10));
+        });
+    }
+}
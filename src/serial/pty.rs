@@ -0,0 +1,150 @@
+use nix::{
+    fcntl::{FcntlArg, OFlag, fcntl},
+    libc,
+    pty::{PtyMaster, grantpt, posix_openpt, ptsname_r, unlockpt},
+    sys::termios::{self, SetArg},
+};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+};
+use tokio::io::unix::AsyncFd;
+
+use super::{Error, UartStimulus};
+
+/// Connects a simulated uart to a pseudo-terminal of the host
+///
+/// Anything that can open a serial port, like picocom, minicom or pyserial, can then
+/// talk to the simulated firmware through [PtyBridge::path].
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use embedded_hal_sim::serial::{Uart, pty::PtyBridge};
+/// # async fn example() {
+/// let (uart, stimulus) = Uart::new(Duration::from_millis(10), 64);
+/// let bridge = PtyBridge::new(stimulus).unwrap();
+/// println!("uart available at {}", bridge.path().display());
+/// tokio::spawn(bridge.run());
+/// # }
+/// ```
+pub struct PtyBridge {
+    uart: UartStimulus,
+    master: PtyMaster,
+    /// Kept open so that reading the master does not fail while no client is connected
+    _slave: File,
+    path: PathBuf,
+}
+
+impl PtyBridge {
+    /// Create a new pseudo-terminal in raw mode for `uart`
+    pub fn new(uart: UartStimulus) -> io::Result<Self> {
+        let master = posix_openpt(OFlag::O_RDWR | OFlag::O_NOCTTY)?;
+        grantpt(&master)?;
+        unlockpt(&master)?;
+        let path = PathBuf::from(ptsname_r(&master)?);
+
+        let slave = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(&path)?;
+        let mut settings = termios::tcgetattr(&slave)?;
+        termios::cfmakeraw(&mut settings);
+        termios::tcsetattr(&slave, SetArg::TCSANOW, &settings)?;
+
+        fcntl(&master, FcntlArg::F_SETFL(OFlag::O_NONBLOCK))?;
+
+        log::info!("Simulated uart available at {}", path.display());
+        Ok(Self {
+            uart,
+            master,
+            _slave: slave,
+            path,
+        })
+    }
+
+    /// Path of the pseudo-terminal, e.g. `/dev/pts/3`
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Forward bytes in both directions, this only returns once the uart is dropped
+    ///
+    /// This needs to be run from a tokio executor
+    pub async fn run(self) -> io::Result<()> {
+        let Self {
+            mut uart, master, ..
+        } = self;
+        // SAFETY: PtyMaster owns its file descriptor and never replaces it
+        let master = unsafe { AsyncFd::register(master)? };
+        let mut from_pty = [0; 256];
+        let mut from_uart = [0; 256];
+
+        loop {
+            tokio::select! {
+                guard = master.readable() => {
+                    let n = match guard?.try_io(|fd| fd.get_ref().read(&mut from_pty)) {
+                        Ok(n) => n?,
+                        Err(_would_block) => continue,
+                    };
+                    uart.write(&from_pty[..n]).await?;
+                }
+                n = embedded_io_async::Read::read(&mut uart, &mut from_uart) => match n {
+                    Ok(n) => write_all(&master, &from_uart[..n]).await?,
+                    Err(Error::Disconnected) => return Err(Error::Disconnected.into()),
+                    // A pseudo-terminal has no way to pass on line errors
+                    Err(e) => log::warn!("Uart {e} not passed on"),
+                }
+            }
+        }
+    }
+}
+
+async fn write_all(master: &AsyncFd<PtyMaster>, mut buf: &[u8]) -> io::Result<()> {
+    while !buf.is_empty() {
+        let mut guard = master.writable().await?;
+        if let Ok(n) = guard.try_io(|fd| fd.get_ref().write(buf)) {
+            buf = &buf[n?..];
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::PtyBridge;
+    use crate::serial::Uart;
+    use embedded_io_async::Read;
+    use std::{
+        fs::OpenOptions,
+        io::{Read as _, Write},
+        time::Duration,
+    };
+
+    #[tokio::test]
+    async fn test() {
+        let (mut uart, stimulus) = Uart::new(Duration::from_millis(10), 16);
+        let bridge = PtyBridge::new(stimulus).unwrap();
+        let mut terminal = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(bridge.path())
+            .unwrap();
+        tokio::spawn(bridge.run());
+
+        terminal.write_all(b"AT\r\n").unwrap();
+        let mut buf = [0; 4];
+        uart.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"AT\r\n");
+
+        uart.write(b"OK\r\n").await.unwrap();
+        let reply = tokio::task::spawn_blocking(move || {
+            let mut buf = [0; 4];
+            terminal.read_exact(&mut buf).unwrap();
+            buf
+        });
+        assert_eq!(&reply.await.unwrap(), b"OK\r\n");
+    }
+}
@@ -0,0 +1,245 @@
+use embedded_hal::digital::PinState;
+use futures::{
+    StreamExt,
+    channel::mpsc,
+    future::{self, Either},
+    stream,
+};
+use std::{pin::pin, time::Duration};
+
+use super::{
+    Uart,
+    line::{Frame, SharedFill, Symbol},
+};
+use crate::{
+    gpio::{Input, Observe},
+    time,
+};
+
+/// A half-duplex multi-drop bus, e.g. RS-485
+///
+/// Every node attached to the bus is a [Uart] with a driver enable pin. A character
+/// only makes it onto the bus if the driver enable of its node is high when it starts,
+/// and characters from different nodes overlapping in time garble each other and are
+/// received as framing errors. Every node receives everything on the bus, including
+/// the echo of what it sent itself.
+///
+/// The bus needs [Bus::run] to be polled to pass characters on.
+///
+/// ```
+/// # use std::time::Duration;
+/// # use embedded_hal::digital::PinState;
+/// # use embedded_hal_sim::{gpio, serial::rs485::Bus};
+/// let mut bus = Bus::new(Duration::from_millis(10), 64);
+/// let (de_sense, de) = gpio::new(PinState::Low);
+/// let uart = bus.attach(de_sense);
+/// ```
+pub struct Bus {
+    timeout: Duration,
+    buffer_size: usize,
+    nodes: Vec<Node>,
+    receivers: Vec<mpsc::Receiver<Frame>>,
+}
+
+struct Node {
+    driver_enable: Input,
+    tx: mpsc::Sender<Frame>,
+    /// Characters waiting to be read by the node
+    fill: SharedFill,
+    /// Characters sent by the node that the bus has not taken yet
+    peer_fill: SharedFill,
+    /// A character was lost because the buffer of the node was full
+    overrun: bool,
+}
+
+/// A character sent by a node that has not been passed on yet
+struct Pending {
+    node: usize,
+    frame: Frame,
+    /// Whether the driver was enabled when the character started
+    driven: Option<bool>,
+    collided: bool,
+}
+
+impl Bus {
+    /// `timeout` and `buffer_size` are used for the uarts of every node, see [Uart::new]
+    pub fn new(timeout: Duration, buffer_size: usize) -> Self {
+        Self {
+            timeout,
+            buffer_size,
+            nodes: Vec::new(),
+            receivers: Vec::new(),
+        }
+    }
+
+    /// Attach a node that drives the bus while `driver_enable` is high
+    pub fn attach(&mut self, driver_enable: Input) -> Uart {
+        let (rx_sender, rx_receiver) = mpsc::channel(self.buffer_size);
+        let (tx_sender, tx_receiver) = mpsc::channel(self.buffer_size);
+        let (fill, peer_fill) = (SharedFill::default(), SharedFill::default());
+        self.nodes.push(Node {
+            driver_enable,
+            tx: rx_sender,
+            fill: fill.clone(),
+            peer_fill: peer_fill.clone(),
+            overrun: false,
+        });
+        self.receivers.push(tx_receiver);
+        Uart::from_channels(rx_receiver, tx_sender, fill, peer_fill, self.timeout)
+    }
+
+    /// Pass characters between the nodes until all of them are dropped
+    pub async fn run(mut self) {
+        let receivers = self.receivers.drain(..).enumerate();
+        let mut frames =
+            stream::select_all(receivers.map(|(node, rx)| rx.map(move |frame| (node, frame))));
+        let mut pending = Vec::new();
+
+        loop {
+            self.update(&mut pending);
+            let next_event = pending
+                .iter()
+                .map(|p: &Pending| match p.driven {
+                    None => p.frame.start,
+                    Some(_) => p.frame.end,
+                })
+                .min();
+            let wait = async {
+                match next_event {
+                    Some(instant) => time::sleep(instant - time::now()).await,
+                    None => future::pending().await,
+                }
+            };
+
+            match future::select(frames.next(), pin!(wait)).await {
+                Either::Left((Some((node, frame)), _)) => {
+                    self.nodes[node].peer_fill.lock().pop();
+                    // Overruns happen in the receiver, they are never on the bus
+                    if frame.symbol != Symbol::Overrun {
+                        pending.push(Pending {
+                            node,
+                            frame,
+                            driven: None,
+                            collided: false,
+                        });
+                    }
+                }
+                Either::Left((None, _)) => return,
+                Either::Right(_) => {}
+            }
+        }
+    }
+
+    fn update(&mut self, pending: &mut Vec<Pending>) {
+        let now = time::now();
+        for p in pending.iter_mut() {
+            if p.driven.is_none() && p.frame.start <= now {
+                let driver_enable = &self.nodes[p.node].driver_enable;
+                p.driven = Some(driver_enable.level() == PinState::High);
+            }
+        }
+
+        // Characters from different nodes overlapping on the bus garble each other
+        for i in 0..pending.len() {
+            let a = &pending[i];
+            let collided = a.driven == Some(true)
+                && pending.iter().any(|b| {
+                    b.driven == Some(true)
+                        && b.node != a.node
+                        && a.frame.start < b.frame.end
+                        && b.frame.start < a.frame.end
+                });
+            pending[i].collided |= collided;
+        }
+
+        let (mut ended, rest) = pending
+            .drain(..)
+            .partition::<Vec<_>, _>(|p| p.driven.is_some() && p.frame.end <= now);
+        *pending = rest;
+        ended.sort_by_key(|p| p.frame.end);
+        for p in ended.into_iter().filter(|p| p.driven == Some(true)) {
+            let symbol = match p.collided {
+                true => Symbol::FramingError,
+                false => p.frame.symbol,
+            };
+            self.deliver(Frame { symbol, ..p.frame });
+        }
+    }
+
+    fn deliver(&mut self, frame: Frame) {
+        for node in &mut self.nodes {
+            if node.overrun {
+                let overrun = Frame {
+                    symbol: Symbol::Overrun,
+                    ..frame
+                };
+                node.overrun = !node.try_send(overrun);
+            }
+            if !node.overrun {
+                node.overrun = !node.try_send(frame);
+            }
+        }
+    }
+}
+
+impl Node {
+    fn try_send(&mut self, frame: Frame) -> bool {
+        let sent = self.tx.try_send(frame).is_ok();
+        if sent {
+            self.fill.lock().push();
+        }
+        sent
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Bus;
+    use crate::{gpio, serial::Error, time::VirtualClock};
+    use embedded_hal::digital::{OutputPin, PinState};
+    use embedded_io_async::{Read, Write};
+    use std::time::Duration;
+
+    #[test]
+    fn test() {
+        let clock = VirtualClock::new();
+        let mut bus = Bus::new(Duration::from_millis(10), 16);
+        let nodes = [(); 3].map(|_| {
+            let (de_sense, de) = gpio::new(PinState::Low);
+            (bus.attach(de_sense), de)
+        });
+
+        let scenario = async move {
+            let [
+                (mut master, mut master_de),
+                (mut a, mut a_de),
+                (mut b, mut b_de),
+            ] = nodes;
+            let mut buf = [0; 1];
+
+            // Every node receives what is sent, including the sender itself
+            master_de.set_high().unwrap();
+            master.write_all(b"?").await.unwrap();
+            master.flush().await.unwrap();
+            master_de.set_low().unwrap();
+            for node in [&mut master, &mut a, &mut b] {
+                node.read_exact(&mut buf).await.unwrap();
+                assert_eq!(&buf, b"?");
+            }
+
+            // Nothing reaches the bus without driver enable
+            a.write_all(b"x").await.unwrap();
+            a.flush().await.unwrap();
+
+            a_de.set_high().unwrap();
+            b_de.set_high().unwrap();
+            a.write_all(b"a").await.unwrap();
+            b.write_all(b"b").await.unwrap();
+            for node in [&mut master, &mut a, &mut b] {
+                assert_eq!(node.read(&mut buf).await, Err(Error::Framing));
+                assert_eq!(node.read(&mut buf).await, Err(Error::Framing));
+            }
+        };
+        clock.block_on(async { futures::join!(bus.run(), scenario) });
+    }
+}
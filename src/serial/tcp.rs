@@ -0,0 +1,251 @@
+use std::{
+    io,
+    net::{SocketAddr, ToSocketAddrs},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use super::{Error, UartStimulus};
+
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+
+const BINARY: u8 = 0;
+const ECHO: u8 = 1;
+const SUPPRESS_GO_AHEAD: u8 = 3;
+
+/// Exposes a simulated uart on a tcp port
+///
+/// One client at a time is connected to the uart. Once it disconnects the next one is
+/// accepted, and anything the firmware sends while no client is connected is lost just
+/// like with an unplugged cable.
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use embedded_hal_sim::serial::{Uart, tcp::TcpBridge};
+/// # async fn example() {
+/// let (uart, stimulus) = Uart::new(Duration::from_millis(10), 64);
+/// let bridge = TcpBridge::bind("127.0.0.1:5000", stimulus).unwrap();
+/// tokio::spawn(bridge.run());
+/// # }
+/// ```
+pub struct TcpBridge {
+    uart: UartStimulus,
+    listener: std::net::TcpListener,
+    telnet: bool,
+}
+
+impl TcpBridge {
+    pub fn bind(addr: impl ToSocketAddrs, uart: UartStimulus) -> io::Result<Self> {
+        let listener = std::net::TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        log::info!("Simulated uart available at {}", listener.local_addr()?);
+        Ok(Self {
+            uart,
+            listener,
+            telnet: false,
+        })
+    }
+
+    /// Speak telnet instead of passing raw bytes
+    ///
+    /// The client is asked for binary, character at a time mode and telnet commands are
+    /// stripped from what it sends. Com port options (RFC 2217) are accepted but ignored.
+    pub fn set_telnet(&mut self, enabled: bool) {
+        self.telnet = enabled;
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Forward bytes between the uart and the connected client, this only returns once
+    /// the uart is dropped
+    ///
+    /// This needs to be run from a tokio executor
+    pub async fn run(self) -> io::Result<()> {
+        let Self {
+            mut uart,
+            listener,
+            telnet,
+        } = self;
+        let listener = TcpListener::from_std(listener)?;
+        let mut discarded = [0; 256];
+
+        loop {
+            let (stream, peer) = tokio::select! {
+                accepted = listener.accept() => accepted?,
+                n = embedded_io_async::Read::read(&mut uart, &mut discarded) => match n {
+                    Err(Error::Disconnected) => return Err(Error::Disconnected.into()),
+                    _ => continue,
+                }
+            };
+            log::info!("Uart client {peer} connected");
+            serve(&mut uart, stream, telnet).await?;
+            log::info!("Uart client {peer} disconnected");
+        }
+    }
+}
+
+/// Forward bytes until the client disconnects, only uart errors are returned
+async fn serve(uart: &mut UartStimulus, mut stream: TcpStream, telnet: bool) -> Result<(), Error> {
+    let mut decoder = telnet.then(Telnet::default);
+    if telnet {
+        let negotiation = [
+            [IAC, WILL, BINARY],
+            [IAC, DO, BINARY],
+            [IAC, WILL, ECHO],
+            [IAC, WILL, SUPPRESS_GO_AHEAD],
+        ];
+        if let Err(e) = stream.write_all(negotiation.as_flattened()).await {
+            log::warn!("Failed to write to uart client: {e}");
+            return Ok(());
+        }
+    }
+
+    let mut from_client = [0; 256];
+    let mut from_uart = [0; 256];
+    let mut buf = Vec::new();
+    loop {
+        tokio::select! {
+            n = stream.read(&mut from_client) => {
+                let n = match n {
+                    Ok(0) => return Ok(()),
+                    Ok(n) => n,
+                    Err(e) => {
+                        log::warn!("Failed to read from uart client: {e}");
+                        return Ok(());
+                    }
+                };
+                let data = match &mut decoder {
+                    Some(decoder) => {
+                        buf.clear();
+                        decoder.decode(&from_client[..n], &mut buf);
+                        &buf[..]
+                    }
+                    None => &from_client[..n],
+                };
+                uart.write(data).await?;
+            }
+            n = embedded_io_async::Read::read(uart, &mut from_uart) => {
+                let data = match n {
+                    Ok(n) => &from_uart[..n],
+                    Err(Error::Disconnected) => return Err(Error::Disconnected),
+                    Err(e) => {
+                        log::warn!("Uart {e} not passed on");
+                        continue;
+                    }
+                };
+                let data = if telnet {
+                    buf.clear();
+                    escape(data, &mut buf);
+                    &buf[..]
+                } else {
+                    data
+                };
+                if let Err(e) = stream.write_all(data).await {
+                    log::warn!("Failed to write to uart client: {e}");
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+/// Where in a telnet command the decoder is
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum Telnet {
+    #[default]
+    Data,
+    Command,
+    Negotiation,
+    Subnegotiation,
+    SubnegotiationCommand,
+}
+
+impl Telnet {
+    /// Strip telnet commands from `input`, appending the remaining data to `output`
+    fn decode(&mut self, input: &[u8], output: &mut Vec<u8>) {
+        for &byte in input {
+            *self = match (*self, byte) {
+                (Telnet::Data, IAC) => Telnet::Command,
+                (Telnet::Data, _) | (Telnet::Command, IAC) => {
+                    output.push(byte);
+                    Telnet::Data
+                }
+                (Telnet::Command, WILL..=DONT) => Telnet::Negotiation,
+                (Telnet::Command, SB) => Telnet::Subnegotiation,
+                (Telnet::Command | Telnet::Negotiation, _) => Telnet::Data,
+                (Telnet::Subnegotiation, IAC) => Telnet::SubnegotiationCommand,
+                (Telnet::SubnegotiationCommand, SE) => Telnet::Data,
+                (Telnet::Subnegotiation | Telnet::SubnegotiationCommand, _) => {
+                    Telnet::Subnegotiation
+                }
+            }
+        }
+    }
+}
+
+/// Double every IAC in `input` so the client does not take it for a command
+fn escape(input: &[u8], output: &mut Vec<u8>) {
+    for &byte in input {
+        if byte == IAC {
+            output.push(IAC);
+        }
+        output.push(byte);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{IAC, SB, SE, TcpBridge, Telnet, WILL, escape};
+    use crate::serial::Uart;
+    use embedded_io_async::Read;
+    use std::time::Duration;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    #[tokio::test]
+    async fn reconnect() {
+        let (mut uart, stimulus) = Uart::new(Duration::from_millis(10), 16);
+        let bridge = TcpBridge::bind("127.0.0.1:0", stimulus).unwrap();
+        let addr = bridge.local_addr().unwrap();
+        tokio::spawn(bridge.run());
+
+        for _ in 0..2 {
+            let mut client = TcpStream::connect(addr).await.unwrap();
+            client.write_all(b"AT\r\n").await.unwrap();
+            let mut buf = [0; 4];
+            uart.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"AT\r\n");
+
+            uart.write(b"OK\r\n").await.unwrap();
+            client.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"OK\r\n");
+        }
+    }
+
+    #[test]
+    fn telnet() {
+        let mut decoder = Telnet::default();
+        let mut data = Vec::new();
+        decoder.decode(
+            &[b'a', IAC, WILL, 1, b'b', IAC, IAC, IAC, SB, 44],
+            &mut data,
+        );
+        decoder.decode(&[1, IAC, SE, b'c'], &mut data);
+        assert_eq!(data, [b'a', b'b', IAC, b'c']);
+
+        let mut escaped = Vec::new();
+        escape(&[1, IAC, 2], &mut escaped);
+        assert_eq!(escaped, [1, IAC, IAC, 2]);
+    }
+}
@@ -0,0 +1,333 @@
+use embedded_hal::{
+    digital::{InputPin, OutputPin},
+    spi::{ErrorType, Operation},
+};
+use parking_lot::Mutex;
+use std::{sync::Arc, time::Duration};
+
+use crate::gpio::{Input, Output};
+
+/// Byte shifted in on MISO when no device drives the line
+pub const IDLE_BYTE: u8 = 0xFF;
+
+/// A simulated device on an spi bus
+///
+/// This is implemented by the stimulus side to answer the transfers done by the
+/// application code. A byte is shifted in for every byte shifted out, just like on
+/// real hardware.
+pub trait SpiDeviceModel {
+    /// Chip select was asserted, a new transaction starts
+    fn select(&mut self) {}
+
+    /// Chip select was released, the transaction is done
+    fn deselect(&mut self) {}
+
+    /// Exchange one byte, `mosi` is the byte sent by the application and the
+    /// returned byte is what the application reads back
+    fn transfer(&mut self, mosi: u8) -> u8;
+}
+
+type SharedModel = Arc<Mutex<dyn SpiDeviceModel + Send>>;
+
+/// A simulated spi bus with any number of attached devices
+///
+/// Every device has its own chip select line which is sampled at every bus
+/// operation. Note that this means that a device will only see that it has been
+/// deselected at the next operation on the bus. Use [SpiDevice] when exact transaction
+/// boundaries matter.
+#[derive(Default)]
+pub struct SpiBus {
+    devices: Vec<BusDevice>,
+}
+
+struct BusDevice {
+    cs: Input,
+    selected: bool,
+    model: SharedModel,
+}
+
+impl SpiBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Attach `model` to the bus, it is selected while `cs` is low
+    ///
+    /// The returned handle can be used by the stimulus side to inspect or modify the model
+    pub fn attach<M: SpiDeviceModel + Send + 'static>(
+        &mut self,
+        mut cs: Input,
+        model: M,
+    ) -> Arc<Mutex<M>> {
+        let model = Arc::new(Mutex::new(model));
+        let selected = cs.is_low().unwrap();
+        if selected {
+            model.lock().select();
+        }
+        self.devices.push(BusDevice {
+            cs,
+            selected,
+            model: Arc::clone(&model) as SharedModel,
+        });
+        model
+    }
+
+    fn update_chip_selects(&mut self) {
+        for device in &mut self.devices {
+            let selected = device.cs.is_low().unwrap();
+            match (device.selected, selected) {
+                (false, true) => device.model.lock().select(),
+                (true, false) => device.model.lock().deselect(),
+                _ => {}
+            }
+            device.selected = selected;
+        }
+    }
+
+    fn transfer_byte(&mut self, mosi: u8) -> u8 {
+        // MISO is pulled high, a selected device pulls bits low
+        self.devices
+            .iter()
+            .filter(|device| device.selected)
+            .fold(IDLE_BYTE, |miso, device| {
+                miso & device.model.lock().transfer(mosi)
+            })
+    }
+
+    fn exchange_in_place(&mut self, words: &mut [u8]) {
+        self.update_chip_selects();
+        for word in words {
+            *word = self.transfer_byte(*word);
+        }
+    }
+
+    fn exchange(&mut self, read: &mut [u8], write: &[u8]) {
+        self.update_chip_selects();
+        for i in 0..read.len().max(write.len()) {
+            let miso = self.transfer_byte(write.get(i).copied().unwrap_or(0));
+            if let Some(dst) = read.get_mut(i) {
+                *dst = miso;
+            }
+        }
+    }
+}
+
+impl ErrorType for SpiBus {
+    type Error = core::convert::Infallible;
+}
+
+impl embedded_hal::spi::SpiBus for SpiBus {
+    fn read(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        self.exchange(words, &[]);
+        Ok(())
+    }
+
+    fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        self.exchange(&mut [], words);
+        Ok(())
+    }
+
+    fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Self::Error> {
+        self.exchange(read, write);
+        Ok(())
+    }
+
+    fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        self.exchange_in_place(words);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.update_chip_selects();
+        Ok(())
+    }
+}
+
+impl embedded_hal_async::spi::SpiBus for SpiBus {
+    async fn read(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        embedded_hal::spi::SpiBus::read(self, words)
+    }
+
+    async fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        embedded_hal::spi::SpiBus::write(self, words)
+    }
+
+    async fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Self::Error> {
+        embedded_hal::spi::SpiBus::transfer(self, read, write)
+    }
+
+    async fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        embedded_hal::spi::SpiBus::transfer_in_place(self, words)
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        embedded_hal::spi::SpiBus::flush(self)
+    }
+}
+
+/// A simulated spi device with exclusive access to its bus
+///
+/// The chip select is driven low for the duration of every transaction. Pass the
+/// [Output] of a [crate::gpio::new] pair to be able to observe it from the stimulus side.
+pub struct SpiDevice {
+    cs: Output,
+    model: SharedModel,
+}
+
+impl SpiDevice {
+    pub fn new<M: SpiDeviceModel + Send + 'static>(
+        mut cs: Output,
+        model: M,
+    ) -> (Self, Arc<Mutex<M>>) {
+        cs.set_high().unwrap();
+        let model = Arc::new(Mutex::new(model));
+        (
+            Self {
+                cs,
+                model: Arc::clone(&model) as SharedModel,
+            },
+            model,
+        )
+    }
+
+    fn select(&mut self) {
+        self.cs.set_low().unwrap();
+        self.model.lock().select();
+    }
+
+    fn deselect(&mut self) {
+        self.model.lock().deselect();
+        self.cs.set_high().unwrap();
+    }
+
+    /// Run a single operation, returns the requested delay if any
+    fn operation(&mut self, operation: &mut Operation<'_, u8>) -> Option<Duration> {
+        let mut model = self.model.lock();
+        match operation {
+            Operation::Read(words) => words.iter_mut().for_each(|w| *w = model.transfer(0)),
+            Operation::Write(words) => words.iter().for_each(|w| {
+                model.transfer(*w);
+            }),
+            Operation::Transfer(read, write) => {
+                for i in 0..read.len().max(write.len()) {
+                    let miso = model.transfer(write.get(i).copied().unwrap_or(0));
+                    if let Some(dst) = read.get_mut(i) {
+                        *dst = miso;
+                    }
+                }
+            }
+            Operation::TransferInPlace(words) => {
+                words.iter_mut().for_each(|w| *w = model.transfer(*w))
+            }
+            Operation::DelayNs(ns) => return Some(Duration::from_nanos(u64::from(*ns))),
+        }
+        None
+    }
+}
+
+impl ErrorType for SpiDevice {
+    type Error = core::convert::Infallible;
+}
+
+impl embedded_hal::spi::SpiDevice for SpiDevice {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Self::Error> {
+        self.select();
+        for operation in operations {
+            if let Some(delay) = self.operation(operation) {
+                crate::delay::block_for(delay);
+            }
+        }
+        self.deselect();
+        Ok(())
+    }
+}
+
+impl embedded_hal_async::spi::SpiDevice for SpiDevice {
+    async fn transaction(
+        &mut self,
+        operations: &mut [Operation<'_, u8>],
+    ) -> Result<(), Self::Error> {
+        self.select();
+        for operation in operations {
+            if let Some(delay) = self.operation(operation) {
+                crate::sleep(delay).await;
+            }
+        }
+        self.deselect();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{SpiBus, SpiDevice, SpiDeviceModel};
+    use crate::gpio;
+    use embedded_hal::digital::{InputPin, OutputPin, PinState};
+    use embedded_hal::spi::{SpiBus as _, SpiDevice as _};
+
+    /// Echoes the previous byte and counts transactions
+    #[derive(Default)]
+    struct Echo {
+        last: u8,
+        transactions: usize,
+    }
+
+    impl SpiDeviceModel for Echo {
+        fn select(&mut self) {
+            self.last = 0xAA;
+        }
+
+        fn deselect(&mut self) {
+            self.transactions += 1;
+        }
+
+        fn transfer(&mut self, mosi: u8) -> u8 {
+            core::mem::replace(&mut self.last, mosi)
+        }
+    }
+
+    #[test]
+    fn device() {
+        let (mut cs_stimulus, cs) = gpio::new(PinState::Low);
+        let (mut spi, model) = SpiDevice::new(cs, Echo::default());
+        assert!(cs_stimulus.is_high().unwrap());
+
+        let mut buf = [1, 2, 3];
+        spi.transfer_in_place(&mut buf).unwrap();
+        assert_eq!(buf, [0xAA, 1, 2]);
+        assert!(cs_stimulus.is_high().unwrap());
+
+        spi.write(&[4, 5]).unwrap();
+        assert_eq!(model.lock().transactions, 2);
+    }
+
+    #[test]
+    fn bus() {
+        let (cs_a, mut cs_a_out) = gpio::new(PinState::High);
+        let (cs_b, mut cs_b_out) = gpio::new(PinState::High);
+        let mut bus = SpiBus::new();
+        let a = bus.attach(cs_a, Echo::default());
+        let b = bus.attach(cs_b, Echo::default());
+
+        // Nothing selected, MISO idles high
+        let mut buf = [1, 2];
+        bus.transfer_in_place(&mut buf).unwrap();
+        assert_eq!(buf, [0xFF, 0xFF]);
+
+        cs_a_out.set_low().unwrap();
+        let mut buf = [1, 2];
+        bus.transfer_in_place(&mut buf).unwrap();
+        assert_eq!(buf, [0xAA, 1]);
+        cs_a_out.set_high().unwrap();
+        bus.flush().unwrap();
+        assert_eq!(a.lock().transactions, 1);
+
+        cs_b_out.set_low().unwrap();
+        bus.write(&[7]).unwrap();
+        cs_b_out.set_high().unwrap();
+        bus.flush().unwrap();
+        assert_eq!(a.lock().transactions, 1);
+        assert_eq!(b.lock().transactions, 1);
+        assert_eq!(b.lock().last, 7);
+    }
+}
@@ -0,0 +1,588 @@
+use futures::task::ArcWake;
+use parking_lot::Mutex;
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    future::Future,
+    ops::{Add, Sub},
+    pin::{Pin, pin},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    task::{Context, Poll, Waker},
+    thread::{self, Thread},
+    time::Duration,
+};
+
+thread_local! {
+    static CURRENT: RefCell<Option<VirtualClock>> = const { RefCell::new(None) };
+}
+
+/// A point in simulated time, measured from the start of the simulation
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(Duration);
+
+impl Instant {
+    /// The current time of the clock used by this thread, see [now]
+    pub fn now() -> Self {
+        now()
+    }
+
+    /// Time since the start of the simulation
+    pub fn since_start(&self) -> Duration {
+        self.0
+    }
+
+    /// Time elapsed from `earlier` to `self`, zero if `earlier` is later than `self`
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        self.0.saturating_sub(earlier.0)
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        now().duration_since(*self)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Self::Output {
+        Instant(self.0 + rhs)
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, rhs: Instant) -> Self::Output {
+        self.duration_since(rhs)
+    }
+}
+
+/// The current simulated time
+///
+/// This is the time of the [VirtualClock] entered by the current thread, or the real
+/// time since the first call if no virtual clock is in use.
+pub fn now() -> Instant {
+    match current() {
+        Some(clock) => clock.now(),
+        None => real_now(),
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn real_now() -> Instant {
+    static START: std::sync::LazyLock<std::time::Instant> =
+        std::sync::LazyLock::new(std::time::Instant::now);
+    Instant(START.elapsed())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn real_now() -> Instant {
+    let ms = web_sys::window()
+        .and_then(|window| window.performance())
+        .map(|performance| performance.now())
+        .unwrap_or_default();
+    Instant(Duration::from_secs_f64(ms / 1000.0))
+}
+
+pub(crate) fn current() -> Option<VirtualClock> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// A clock where time only moves when told to
+///
+/// Every [sleep], [timeout] and other timing in this crate that is started on a thread
+/// that has entered the clock, see [VirtualClock::enter] and [VirtualClock::block_on],
+/// uses the virtual time instead of the wall clock.
+///
+/// The time is moved forward by [VirtualClock::advance], or automatically to the next
+/// pending timer when all tasks in [VirtualClock::block_on] are idle. This makes
+/// simulations deterministic and lets them run as fast as possible.
+#[derive(Clone)]
+pub struct VirtualClock {
+    state: Arc<Mutex<ClockState>>,
+    auto_advance: bool,
+}
+
+#[derive(Default)]
+struct ClockState {
+    now: Duration,
+    next_id: u64,
+    timers: BTreeMap<(Duration, u64), Waker>,
+}
+
+impl Default for VirtualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VirtualClock {
+    /// A clock that jumps to the next timer whenever all tasks are idle
+    pub fn new() -> Self {
+        Self {
+            state: Default::default(),
+            auto_advance: true,
+        }
+    }
+
+    /// A clock that only advances when [VirtualClock::advance] is called
+    pub fn manual() -> Self {
+        Self {
+            auto_advance: false,
+            ..Self::new()
+        }
+    }
+
+    pub fn now(&self) -> Instant {
+        Instant(self.state.lock().now)
+    }
+
+    /// Move time forward by `duration`, waking every timer that expires
+    ///
+    /// This may be called from any thread.
+    pub fn advance(&self, duration: Duration) {
+        let mut state = self.state.lock();
+        state.now += duration;
+        let expired = Self::take_expired(&mut state);
+        drop(state);
+        expired.into_values().for_each(Waker::wake);
+    }
+
+    /// Move time forward to the first pending timer, returns false if there is none
+    pub fn advance_to_next_timer(&self) -> bool {
+        let mut state = self.state.lock();
+        let Some(&(deadline, _)) = state.timers.keys().next() else {
+            return false;
+        };
+        state.now = state.now.max(deadline);
+        let expired = Self::take_expired(&mut state);
+        drop(state);
+        expired.into_values().for_each(Waker::wake);
+        true
+    }
+
+    fn take_expired(state: &mut ClockState) -> BTreeMap<(Duration, u64), Waker> {
+        let later = state.timers.split_off(&(state.now, u64::MAX));
+        core::mem::replace(&mut state.timers, later)
+    }
+
+    /// Use this clock for all timing started on the current thread until the guard is dropped
+    ///
+    /// This can be used to run the simulation on another single threaded executor.
+    /// Note that time will then only advance through [VirtualClock::advance].
+    pub fn enter(&self) -> EnterGuard {
+        let previous = CURRENT.with(|current| current.borrow_mut().replace(self.clone()));
+        EnterGuard { previous }
+    }
+
+    /// Run `future` to completion on the current thread using this clock
+    ///
+    /// Combine several tasks with e.g. [futures::join] to run them on the same clock.
+    ///
+    /// A clock from [VirtualClock::new] jumps to the next timer as soon as every task
+    /// polled here is idle, even when one of them is waiting for something that
+    /// another thread will do. Timeouts can then expire before that thread got to run.
+    /// Use [VirtualClock::manual] and [VirtualClock::advance] when tasks depend on
+    /// other threads.
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        let _guard = self.enter();
+        let mut future = pin!(future);
+        let thread_waker = Arc::new(ThreadWaker {
+            woken: AtomicBool::new(true),
+            thread: thread::current(),
+        });
+        let waker = futures::task::waker(Arc::clone(&thread_waker));
+        let mut cx = Context::from_waker(&waker);
+
+        loop {
+            if thread_waker.woken.swap(false, Ordering::SeqCst) {
+                if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                    return output;
+                }
+                continue;
+            }
+
+            // Every task is waiting, either on a timer or on something outside of this thread
+            if self.auto_advance && self.advance_to_next_timer() {
+                continue;
+            }
+            thread::park();
+        }
+    }
+
+    fn register(&self, deadline: Duration, id: &mut Option<u64>, waker: &Waker) -> Poll<()> {
+        let mut state = self.state.lock();
+        if deadline <= state.now {
+            return Poll::Ready(());
+        }
+        let id = *id.get_or_insert_with(|| {
+            state.next_id += 1;
+            state.next_id
+        });
+        state.timers.insert((deadline, id), waker.clone());
+        Poll::Pending
+    }
+
+    fn unregister(&self, deadline: Duration, id: u64) {
+        self.state.lock().timers.remove(&(deadline, id));
+    }
+}
+
+/// Returned by [VirtualClock::enter]
+pub struct EnterGuard {
+    previous: Option<VirtualClock>,
+}
+
+impl Drop for EnterGuard {
+    fn drop(&mut self) {
+        CURRENT.with(|current| *current.borrow_mut() = self.previous.take());
+    }
+}
+
+struct ThreadWaker {
+    woken: AtomicBool,
+    thread: Thread,
+}
+
+impl ArcWake for ThreadWaker {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.woken.store(true, Ordering::SeqCst);
+        arc_self.thread.unpark();
+    }
+}
+
+/// Wait until `duration` has passed on the clock used by this thread
+///
+/// The clock is picked when the returned future is first polled.
+pub fn sleep(duration: Duration) -> Sleep {
+    Sleep {
+        duration,
+        inner: SleepInner::Unstarted,
+    }
+}
+
+/// Future returned by [sleep]
+pub struct Sleep {
+    duration: Duration,
+    inner: SleepInner,
+}
+
+enum SleepInner {
+    Unstarted,
+    Virtual {
+        clock: VirtualClock,
+        deadline: Duration,
+        id: Option<u64>,
+    },
+    Real(RealSleep),
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        if let SleepInner::Unstarted = this.inner {
+            // Nothing to wait for, don't arm a real timer
+            if this.duration.is_zero() {
+                return Poll::Ready(());
+            }
+            this.inner = match current() {
+                Some(clock) => SleepInner::Virtual {
+                    deadline: clock.now().0 + this.duration,
+                    clock,
+                    id: None,
+                },
+                None => SleepInner::Real(real_sleep(this.duration)),
+            };
+        }
+
+        match &mut this.inner {
+            SleepInner::Unstarted => unreachable!(),
+            SleepInner::Virtual {
+                clock,
+                deadline,
+                id,
+            } => clock.register(*deadline, id, cx.waker()),
+            SleepInner::Real(sleep) => sleep.as_mut().poll(cx),
+        }
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let SleepInner::Virtual {
+            clock,
+            deadline,
+            id: Some(id),
+        } = &self.inner
+        {
+            clock.unregister(*deadline, *id);
+        }
+    }
+}
+
+#[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+type RealSleep = Pin<Box<tokio::time::Sleep>>;
+
+#[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+fn real_sleep(duration: Duration) -> RealSleep {
+    Box::pin(tokio::time::sleep(duration))
+}
+
+#[cfg(target_arch = "wasm32")]
+type RealSleep = Pin<Box<gloo_timers::future::TimeoutFuture>>;
+
+#[cfg(target_arch = "wasm32")]
+fn real_sleep(duration: Duration) -> RealSleep {
+    Box::pin(gloo_timers::future::sleep(duration))
+}
+
+/// Without a runtime providing timers, all sleeps share one timer thread
+#[cfg(not(any(feature = "tokio", target_arch = "wasm32")))]
+type RealSleep = Pin<Box<ThreadSleep>>;
+
+#[cfg(not(any(feature = "tokio", target_arch = "wasm32")))]
+fn real_sleep(duration: Duration) -> RealSleep {
+    Box::pin(ThreadSleep {
+        deadline: std::time::Instant::now() + duration,
+        id: None,
+    })
+}
+
+#[cfg(not(any(feature = "tokio", target_arch = "wasm32")))]
+struct ThreadSleep {
+    deadline: std::time::Instant,
+    id: Option<u64>,
+}
+
+#[cfg(not(any(feature = "tokio", target_arch = "wasm32")))]
+struct TimerThread {
+    state: Mutex<TimerThreadState>,
+    thread: Thread,
+}
+
+#[cfg(not(any(feature = "tokio", target_arch = "wasm32")))]
+#[derive(Default)]
+struct TimerThreadState {
+    next_id: u64,
+    timers: BTreeMap<(std::time::Instant, u64), Waker>,
+}
+
+#[cfg(not(any(feature = "tokio", target_arch = "wasm32")))]
+static TIMER_THREAD: std::sync::LazyLock<TimerThread> = std::sync::LazyLock::new(|| {
+    let handle = thread::Builder::new()
+        .name("timer".into())
+        .spawn(run_timer_thread)
+        .expect("failed to spawn the timer thread");
+    TimerThread {
+        state: Default::default(),
+        thread: handle.thread().clone(),
+    }
+});
+
+/// Wake every expired sleep, then park until the next deadline or a new sleep
+#[cfg(not(any(feature = "tokio", target_arch = "wasm32")))]
+fn run_timer_thread() {
+    loop {
+        let mut state = TIMER_THREAD.state.lock();
+        let later = state
+            .timers
+            .split_off(&(std::time::Instant::now(), u64::MAX));
+        let expired = core::mem::replace(&mut state.timers, later);
+        let next = state.timers.keys().next().map(|&(deadline, _)| deadline);
+        drop(state);
+        expired.into_values().for_each(Waker::wake);
+        match next {
+            Some(deadline) => {
+                thread::park_timeout(deadline.saturating_duration_since(std::time::Instant::now()))
+            }
+            None => thread::park(),
+        }
+    }
+}
+
+#[cfg(not(any(feature = "tokio", target_arch = "wasm32")))]
+impl Future for ThreadSleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        if std::time::Instant::now() >= this.deadline {
+            return Poll::Ready(());
+        }
+        let mut state = TIMER_THREAD.state.lock();
+        let id = *this.id.get_or_insert_with(|| {
+            state.next_id += 1;
+            state.next_id
+        });
+        state.timers.insert((this.deadline, id), cx.waker().clone());
+        drop(state);
+        TIMER_THREAD.thread.unpark();
+        Poll::Pending
+    }
+}
+
+#[cfg(not(any(feature = "tokio", target_arch = "wasm32")))]
+impl Drop for ThreadSleep {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            TIMER_THREAD
+                .state
+                .lock()
+                .timers
+                .remove(&(self.deadline, id));
+        }
+    }
+}
+
+/// Tick every `period` on the clock used by this thread, starting with the first tick
+///
+/// Like [sleep], the clock is picked when the first tick is polled.
+pub fn interval(period: Duration) -> Interval {
+    assert!(!period.is_zero(), "interval period must not be zero");
+    Interval { period, next: None }
+}
+
+/// Returned by [interval]
+///
+/// With the `tokio` feature this used to be a re-export of `tokio::time::Interval`.
+/// Use `tokio::time::interval` directly where a tokio interval on the wall clock is
+/// needed.
+pub struct Interval {
+    period: Duration,
+    next: Option<Instant>,
+}
+
+impl Interval {
+    /// Wait for the next tick and return when it was due
+    ///
+    /// The first tick completes immediately. Ticks missed by a slow caller complete
+    /// immediately one after another.
+    pub async fn tick(&mut self) -> Instant {
+        let next = *self.next.get_or_insert_with(now);
+        sleep(next - now()).await;
+        self.next = Some(next + self.period);
+        next
+    }
+
+    /// Make the next tick due one period from now
+    pub fn reset(&mut self) {
+        self.next = Some(now() + self.period);
+    }
+
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+}
+
+/// Error returned by [timeout] when the duration passed before the future completed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed;
+
+/// Run `future` but give up if it has not completed within `duration`
+pub async fn timeout<F: Future>(duration: Duration, future: F) -> Result<F::Output, Elapsed> {
+    use futures::FutureExt;
+
+    futures::select_biased! {
+        output = pin!(future.fuse()) => Ok(output),
+        _ = sleep(duration).fuse() => Err(Elapsed),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Elapsed, Instant, VirtualClock, interval, now, sleep, timeout};
+    use futures::FutureExt;
+    use std::time::Duration;
+
+    #[test]
+    fn virtual_time() {
+        let clock = VirtualClock::new();
+        let start = std::time::Instant::now();
+
+        let order = clock.block_on(async {
+            let mut order = Vec::new();
+            let a = async {
+                sleep(Duration::from_secs(3600)).await;
+                now()
+            };
+            let b = async {
+                for _ in 0..3 {
+                    sleep(Duration::from_secs(1)).await;
+                    order.push(now().since_start().as_secs());
+                }
+            };
+            let (a, ()) = futures::join!(a, b);
+            assert_eq!(a, Instant::default() + Duration::from_secs(3600));
+            order
+        });
+        assert_eq!(order, [1, 2, 3]);
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        let result = clock.block_on(timeout(
+            Duration::from_millis(10),
+            futures::future::pending::<()>(),
+        ));
+        assert_eq!(result, Err(Elapsed));
+        assert_eq!(
+            clock.now().since_start(),
+            Duration::from_millis(3600 * 1000 + 10)
+        );
+    }
+
+    #[test]
+    fn manual() {
+        let clock = VirtualClock::manual();
+        let stepper = clock.clone();
+        let handle = std::thread::spawn(move || {
+            for _ in 0..10 {
+                std::thread::sleep(Duration::from_millis(1));
+                stepper.advance(Duration::from_millis(100));
+            }
+        });
+        clock.block_on(sleep(Duration::from_millis(500)));
+        assert!(clock.now().since_start() >= Duration::from_millis(500));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn interval_ticks() {
+        let clock = VirtualClock::new();
+        let ticks = clock.block_on(async {
+            let mut interval = interval(Duration::from_millis(10));
+            let mut ticks = Vec::new();
+            for i in 0..4 {
+                ticks.push(interval.tick().await.since_start().as_millis());
+                if i == 1 {
+                    // Miss the next tick
+                    sleep(Duration::from_millis(15)).await;
+                }
+            }
+            ticks
+        });
+        assert_eq!(ticks, [0, 10, 20, 30]);
+        assert_eq!(clock.now().since_start(), Duration::from_millis(30));
+    }
+
+    #[test]
+    fn zero() {
+        // Completes without a timer, even outside of a runtime
+        assert!(sleep(Duration::ZERO).now_or_never().is_some());
+    }
+
+    #[cfg(not(feature = "tokio"))]
+    #[test]
+    fn timer_thread() {
+        let start = std::time::Instant::now();
+        futures::executor::block_on(async {
+            let short = sleep(Duration::from_millis(10));
+            let long = sleep(Duration::from_millis(30));
+            futures::join!(short, long);
+        });
+        assert!(start.elapsed() >= Duration::from_millis(30));
+    }
+}
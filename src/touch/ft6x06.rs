@@ -0,0 +1,148 @@
+use embedded_graphics::prelude::Size;
+
+use super::TouchStimulus;
+use crate::{
+    gpio::Output,
+    i2c::{Direction, I2cDeviceModel},
+};
+
+/// Default i2c address of the controller
+pub const ADDRESS: u8 = 0x38;
+
+const TD_STATUS: usize = 0x02;
+/// Registers of the first touch point, the second one follows
+const P1_XH: usize = 0x03;
+const POINT_REGISTERS: usize = 6;
+const MAX_POINTS: usize = 2;
+const CHIP_ID: usize = 0xA3;
+const FIRMWARE_ID: usize = 0xA6;
+const VENDOR_ID: usize = 0xA8;
+
+/// Emulates the ft6x06 controller of capacitive touch panels on i2c
+///
+/// The first byte written selects the register, further writes and reads continue
+/// with the following registers. Up to two touch points are reported in pixels, the
+/// touch registers are sampled when a read starts. `irq` is low while touched.
+pub struct Ft6x06 {
+    touch: TouchStimulus,
+    registers: [u8; 256],
+    pointer: u8,
+    address_phase: bool,
+}
+
+impl Ft6x06 {
+    /// Controller of a panel on a display of `size` pixels
+    pub fn new(size: Size, irq: Output) -> (Self, TouchStimulus) {
+        let touch = TouchStimulus::new(size, irq);
+        let mut registers = [0; 256];
+        registers[CHIP_ID] = 0x06;
+        registers[FIRMWARE_ID] = 0x10;
+        registers[VENDOR_ID] = 0x11;
+        (
+            Self {
+                touch: touch.clone(),
+                registers,
+                pointer: 0,
+                address_phase: false,
+            },
+            touch,
+        )
+    }
+
+    fn sample(&mut self) {
+        let points = self.touch.touches();
+        self.registers[TD_STATUS] = points.len().min(MAX_POINTS) as u8;
+        for i in 0..MAX_POINTS {
+            let start = P1_XH + i * POINT_REGISTERS;
+            let registers = &mut self.registers[start..start + POINT_REGISTERS];
+            let Some(point) = points.get(i) else {
+                registers.fill(0xFF);
+                continue;
+            };
+            let [x_high, x_low] = (point.position.x as u16).to_be_bytes();
+            let [y_high, y_low] = (point.position.y as u16).to_be_bytes();
+            registers.copy_from_slice(&[
+                // Contact event
+                0x80 | x_high & 0x0F,
+                x_low,
+                point.id << 4 | y_high & 0x0F,
+                y_low,
+                point.pressure,
+                0,
+            ]);
+        }
+    }
+}
+
+impl I2cDeviceModel for Ft6x06 {
+    fn start(&mut self, direction: Direction) -> bool {
+        self.address_phase = direction == Direction::Write;
+        if direction == Direction::Read {
+            self.sample();
+        }
+        true
+    }
+
+    fn write(&mut self, byte: u8) -> bool {
+        if std::mem::take(&mut self.address_phase) {
+            self.pointer = byte;
+        } else {
+            self.registers[usize::from(self.pointer)] = byte;
+            self.pointer = self.pointer.wrapping_add(1);
+        }
+        true
+    }
+
+    fn read(&mut self) -> u8 {
+        let byte = self.registers[usize::from(self.pointer)];
+        self.pointer = self.pointer.wrapping_add(1);
+        byte
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ADDRESS, Ft6x06};
+    use crate::{gpio, i2c::I2cBus, touch::TouchPoint};
+    use embedded_graphics::prelude::{Point, Size};
+    use embedded_hal::{
+        digital::{InputPin, PinState},
+        i2c::I2c,
+    };
+
+    #[test]
+    fn test() {
+        let (mut irq, irq_out) = gpio::new(PinState::Low);
+        let (model, touch) = Ft6x06::new(Size::new(240, 320), irq_out);
+        let mut bus = I2cBus::new();
+        bus.attach(ADDRESS, model);
+
+        let mut id = [0];
+        bus.write_read(ADDRESS, &[0xA8], &mut id).unwrap();
+        assert_eq!(id, [0x11]);
+
+        let mut status = [0; 13];
+        bus.write_read(ADDRESS, &[0x02], &mut status).unwrap();
+        assert_eq!(status[0], 0);
+        assert!(irq.is_high().unwrap());
+
+        touch.set_touches(&[
+            TouchPoint {
+                id: 0,
+                position: Point::new(10, 300),
+                pressure: 40,
+            },
+            TouchPoint {
+                id: 1,
+                position: Point::new(200, 20),
+                pressure: 50,
+            },
+        ]);
+        assert!(irq.is_low().unwrap());
+        bus.write_read(ADDRESS, &[0x02], &mut status).unwrap();
+        assert_eq!(
+            status,
+            [2, 0x80, 10, 0x01, 0x2C, 40, 0, 0x80, 200, 0x10, 20, 50, 0]
+        );
+    }
+}
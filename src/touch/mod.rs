@@ -0,0 +1,136 @@
+use embedded_graphics::prelude::{Point, Size};
+use embedded_hal::digital::{OutputPin, PinState};
+use parking_lot::Mutex;
+use std::sync::Arc;
+
+use crate::gpio::Output;
+
+pub mod ft6x06;
+pub mod xpt2046;
+
+/// A finger or stylus on a touch panel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TouchPoint {
+    /// Tells the points of multi-touch apart
+    pub id: u8,
+    /// In pixels of the display under the touch panel
+    pub position: Point,
+    /// From 0 to 255
+    pub pressure: u8,
+}
+
+/// Touches a simulated touch panel, shared with the controller model
+///
+/// The interrupt output of the controller is low while the panel is touched, like the
+/// pen interrupt of resistive and the interrupt of capacitive controllers.
+#[derive(Clone)]
+pub struct TouchStimulus {
+    shared: Arc<Mutex<Shared>>,
+}
+
+struct Shared {
+    size: Size,
+    points: Vec<TouchPoint>,
+    irq: Output,
+    irq_enabled: bool,
+}
+
+impl TouchStimulus {
+    fn new(size: Size, irq: Output) -> Self {
+        let mut shared = Shared {
+            size,
+            points: Vec::new(),
+            irq,
+            irq_enabled: true,
+        };
+        shared.update_irq();
+        Self {
+            shared: Arc::new(Mutex::new(shared)),
+        }
+    }
+
+    /// Touch the panel at all of `points`, replacing the previous touches
+    ///
+    /// Positions outside of the panel are moved to its edge.
+    pub fn set_touches(&self, points: &[TouchPoint]) {
+        let mut shared = self.shared.lock();
+        let bottom_right =
+            Point::new(shared.size.width as i32, shared.size.height as i32) - Point::new(1, 1);
+        shared.points = points
+            .iter()
+            .map(|&point| TouchPoint {
+                position: point
+                    .position
+                    .component_max(Point::zero())
+                    .component_min(bottom_right),
+                ..point
+            })
+            .collect();
+        shared.update_irq();
+    }
+
+    /// Touch a single point with full pressure
+    pub fn press(&self, position: Point) {
+        self.set_touches(&[TouchPoint {
+            id: 0,
+            position,
+            pressure: u8::MAX,
+        }]);
+    }
+
+    pub fn release(&self) {
+        self.set_touches(&[]);
+    }
+
+    pub fn touches(&self) -> Vec<TouchPoint> {
+        self.shared.lock().points.clone()
+    }
+
+    /// Touch where the pointer is pressed on `response`, e.g. the image of a display
+    ///
+    /// Call this every frame. The area of `response` is scaled to the size of the panel.
+    #[cfg(feature = "egui")]
+    pub fn follow_pointer(&self, response: &egui::Response) {
+        let pressed = response.is_pointer_button_down_on();
+        match response.interact_pointer_pos().filter(|_| pressed) {
+            Some(pos) => {
+                let size = self.shared.lock().size;
+                let rect = response.rect;
+                let x = (pos.x - rect.left()) / rect.width() * size.width as f32;
+                let y = (pos.y - rect.top()) / rect.height() * size.height as f32;
+                self.press(Point::new(x as i32, y as i32));
+            }
+            None if !self.touches().is_empty() => self.release(),
+            None => {}
+        }
+    }
+}
+
+impl Shared {
+    fn update_irq(&mut self) {
+        let touched = self.irq_enabled && !self.points.is_empty();
+        let Ok(()) = self.irq.set_state(PinState::from(!touched));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::TouchStimulus;
+    use crate::gpio::{self, Observe};
+    use embedded_graphics::prelude::{Point, Size};
+    use embedded_hal::digital::PinState;
+
+    #[test]
+    fn test() {
+        let (irq, irq_out) = gpio::new(PinState::Low);
+        let touch = TouchStimulus::new(Size::new(320, 240), irq_out);
+        assert_eq!(irq.level(), PinState::High);
+
+        touch.press(Point::new(400, -5));
+        assert_eq!(irq.level(), PinState::Low);
+        assert_eq!(touch.touches()[0].position, Point::new(319, 0));
+
+        touch.release();
+        assert_eq!(irq.level(), PinState::High);
+    }
+}
@@ -0,0 +1,116 @@
+use embedded_graphics::prelude::Size;
+use std::collections::VecDeque;
+
+use super::TouchStimulus;
+use crate::{gpio::Output, spi::SpiDeviceModel};
+
+/// Emulates the xpt2046 (or ads7846) controller of resistive touch panels on spi
+///
+/// Every control byte starts a conversion whose 12 (or 8) bit result is shifted out
+/// in the next two bytes, which may overlap with the next control byte. X and Y span
+/// the whole range of the adc over the panel, with only the first touch point measured
+/// like on a real resistive panel. Z1 and Z2 are made up so that the usual pressure
+/// estimate `z1 + 4095 - z2` grows with the pressure.
+///
+/// `irq` is the pen interrupt output, which is disabled by power down modes 01 and 11.
+pub struct Xpt2046 {
+    touch: TouchStimulus,
+    /// Result bytes not shifted out yet
+    output: VecDeque<u8>,
+}
+
+const CHANNEL_Y: u8 = 0b001;
+const CHANNEL_Z1: u8 = 0b011;
+const CHANNEL_Z2: u8 = 0b100;
+const CHANNEL_X: u8 = 0b101;
+
+impl Xpt2046 {
+    /// Controller of a panel on a display of `size` pixels
+    pub fn new(size: Size, irq: Output) -> (Self, TouchStimulus) {
+        let touch = TouchStimulus::new(size, irq);
+        (
+            Self {
+                touch: touch.clone(),
+                output: VecDeque::new(),
+            },
+            touch,
+        )
+    }
+
+    fn convert(&mut self, control: u8) {
+        let mut shared = self.touch.shared.lock();
+        shared.irq_enabled = control & 0x01 == 0;
+        shared.update_irq();
+
+        let scale = |position: i32, pixels: u32| {
+            (position as u32 * 4095 / pixels.saturating_sub(1).max(1)) as u16
+        };
+        let point = shared.points.first();
+        let z1 = point.map_or(0, |point| u16::from(point.pressure) * 8);
+        let value = match control >> 4 & 0x07 {
+            CHANNEL_X => point.map_or(0, |point| scale(point.position.x, shared.size.width)),
+            CHANNEL_Y => point.map_or(0, |point| scale(point.position.y, shared.size.height)),
+            CHANNEL_Z1 => z1,
+            CHANNEL_Z2 => 4095 - z1,
+            // Temperature, battery and aux inputs
+            _ => 0,
+        };
+
+        // The result starts after one busy clock
+        let word = match control & 0x08 != 0 {
+            true => (value >> 4) << 7,
+            false => value << 3,
+        };
+        self.output = VecDeque::from(word.to_be_bytes());
+    }
+}
+
+impl SpiDeviceModel for Xpt2046 {
+    fn select(&mut self) {
+        self.output.clear();
+    }
+
+    fn transfer(&mut self, mosi: u8) -> u8 {
+        let miso = self.output.pop_front().unwrap_or(0);
+        // Control bytes have the start bit set
+        if mosi & 0x80 != 0 {
+            self.convert(mosi);
+        }
+        miso
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Xpt2046;
+    use crate::{gpio, spi::SpiDevice};
+    use embedded_graphics::prelude::{Point, Size};
+    use embedded_hal::{
+        digital::{InputPin, PinState},
+        spi::SpiDevice as _,
+    };
+
+    #[test]
+    fn test() {
+        let (mut irq, irq_out) = gpio::new(PinState::Low);
+        let (_cs_sense, cs) = gpio::new(PinState::High);
+        let (model, touch) = Xpt2046::new(Size::new(320, 240), irq_out);
+        let (mut spi, _model) = SpiDevice::new(cs, model);
+        assert!(irq.is_high().unwrap());
+
+        touch.press(Point::new(319, 120));
+        assert!(irq.is_low().unwrap());
+
+        // X, Y, Z1 and Z2 with every control byte overlapping the previous result
+        let mut buf = [0xD0, 0, 0x90, 0, 0xB0, 0, 0xC0, 0, 0];
+        spi.transfer_in_place(&mut buf).unwrap();
+        let result = |i: usize| u16::from_be_bytes([buf[i], buf[i + 1]]) >> 3;
+        assert_eq!(result(1), 4095);
+        assert_eq!(u32::from(result(3)), 120 * 4095 / 239);
+        assert!(result(5) + 4095 - result(7) > 4000);
+
+        // Power down mode 01 disables the pen interrupt
+        spi.write(&[0xD1, 0, 0]).unwrap();
+        assert!(irq.is_high().unwrap());
+    }
+}
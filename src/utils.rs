@@ -1,5 +1,32 @@
 #[cfg(not(feature = "tokio"))]
-use std::sync::Arc;
+use std::sync::{Arc, Weak};
+
+/// Carries `None` once the sender is dropped
+#[cfg(not(feature = "tokio"))]
+type Signal<T> = embassy_sync::signal::Signal<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    Option<T>,
+>;
+
+/// Every receiver has a signal of its own since a [Signal] only wakes a single waiter
+#[cfg(not(feature = "tokio"))]
+type Receivers<T> = Arc<parking_lot::Mutex<ReceiverList<T>>>;
+
+#[cfg(not(feature = "tokio"))]
+struct ReceiverList<T> {
+    signals: Vec<Weak<Signal<T>>>,
+    closed: bool,
+}
+
+#[cfg(not(feature = "tokio"))]
+impl<T> Default for ReceiverList<T> {
+    fn default() -> Self {
+        Self {
+            signals: Vec::new(),
+            closed: false,
+        }
+    }
+}
 
 pub fn signal<T: Clone>(_init: T) -> (SignalTx<T>, SignalRx<T>) {
     #[cfg(feature = "tokio")]
@@ -7,29 +34,72 @@ pub fn signal<T: Clone>(_init: T) -> (SignalTx<T>, SignalRx<T>) {
 
     #[cfg(not(feature = "tokio"))]
     let (tx, rx) = {
-        let tx = Arc::new(embassy_sync::signal::Signal::new());
-        let rx = Arc::clone(&tx);
-        (tx, rx)
+        let receivers = Receivers::default();
+        let rx = SignalRx::subscribe(&receivers);
+        (receivers, rx)
     };
 
-    (SignalTx { inner: tx }, SignalRx { inner: rx })
+    #[cfg(feature = "tokio")]
+    let rx = SignalRx { inner: rx };
+
+    (SignalTx { inner: tx }, rx)
 }
 
+/// Receiving end of a signal, every clone sees every value sent
 pub struct SignalRx<T: Clone> {
     #[cfg(feature = "tokio")]
     inner: tokio::sync::watch::Receiver<T>,
 
     #[cfg(not(feature = "tokio"))]
-    inner: Arc<embassy_sync::signal::Signal<embassy_sync::blocking_mutex::raw::NoopRawMutex, T>>,
+    inner: Arc<Signal<T>>,
+    #[cfg(not(feature = "tokio"))]
+    receivers: Receivers<T>,
 }
 
 impl<T: Clone> SignalRx<T> {
-    pub async fn wait(&mut self) -> T {
+    #[cfg(not(feature = "tokio"))]
+    fn subscribe(receivers: &Receivers<T>) -> Self {
+        let inner = Arc::new(Signal::new());
+        let mut list = receivers.lock();
+        list.signals.retain(|receiver| receiver.strong_count() > 0);
+        list.signals.push(Arc::downgrade(&inner));
+        Self {
+            inner,
+            receivers: Arc::clone(receivers),
+        }
+    }
+
+    /// Wait for the next value, `None` once the sender is dropped
+    pub async fn wait(&mut self) -> Option<T> {
         #[cfg(feature = "tokio")]
-        return self.inner.wait_for(|_| true).await.unwrap().clone();
+        {
+            self.inner.changed().await.ok()?;
+            return Some(self.inner.borrow_and_update().clone());
+        }
 
         #[cfg(not(feature = "tokio"))]
-        self.inner.wait().await
+        {
+            // A value sent right before the sender was dropped is still received
+            if let Some(value) = self.inner.try_take() {
+                return value;
+            }
+            if self.receivers.lock().closed {
+                return None;
+            }
+            self.inner.wait().await
+        }
+    }
+}
+
+impl<T: Clone> Clone for SignalRx<T> {
+    fn clone(&self) -> Self {
+        #[cfg(feature = "tokio")]
+        return Self {
+            inner: self.inner.clone(),
+        };
+
+        #[cfg(not(feature = "tokio"))]
+        Self::subscribe(&self.receivers)
     }
 }
 
@@ -38,15 +108,36 @@ pub struct SignalTx<T: Clone> {
     inner: tokio::sync::watch::Sender<T>,
 
     #[cfg(not(feature = "tokio"))]
-    inner: Arc<embassy_sync::signal::Signal<embassy_sync::blocking_mutex::raw::NoopRawMutex, T>>,
+    inner: Receivers<T>,
 }
 
 impl<T: Clone> SignalTx<T> {
     pub fn signal(&mut self, x: T) {
         #[cfg(feature = "tokio")]
-        return self.inner.send(x).unwrap();
+        {
+            // Signalling without anyone listening is fine
+            self.inner.send_replace(x);
+        }
 
         #[cfg(not(feature = "tokio"))]
-        self.inner.signal(x)
+        self.inner
+            .lock()
+            .signals
+            .iter()
+            .filter_map(Weak::upgrade)
+            .for_each(|receiver| receiver.signal(Some(x.clone())));
+    }
+}
+
+#[cfg(not(feature = "tokio"))]
+impl<T: Clone> Drop for SignalTx<T> {
+    fn drop(&mut self) {
+        let mut list = self.inner.lock();
+        list.closed = true;
+        list.signals
+            .iter()
+            .filter_map(Weak::upgrade)
+            .filter(|receiver| !receiver.signaled())
+            .for_each(|receiver| receiver.signal(None));
     }
 }
@@ -0,0 +1,265 @@
+use embedded_hal::digital::PinState;
+use parking_lot::Mutex;
+use std::{io, sync::Arc};
+
+use crate::{
+    gpio::{Listener, Observe},
+    time,
+};
+
+/// Records level changes of gpio pins to a value change dump
+///
+/// The resulting file can be viewed in e.g. GTKWave or Surfer. Timestamps come from
+/// the simulator clock, see [crate::time], with a resolution of one nanosecond.
+///
+/// ```no_run
+/// # use embedded_hal::digital::PinState;
+/// # use embedded_hal_sim::{gpio, vcd};
+/// let (_clk_stimulus, clk) = gpio::new(PinState::Low);
+/// let (_data_stimulus, data) = gpio::new(PinState::Low);
+///
+/// let mut recorder = vcd::Recorder::new(std::fs::File::create("trace.vcd").unwrap());
+/// recorder.add("clk", &clk);
+/// recorder.add("data", &data);
+/// ```
+pub struct Recorder<W: io::Write + Send + 'static> {
+    inner: Arc<Mutex<Inner<W>>>,
+    listeners: Vec<Listener>,
+}
+
+struct Inner<W> {
+    writer: Option<W>,
+    signals: Vec<Signal>,
+    header_written: bool,
+    last_timestamp: Option<u128>,
+    error: Option<io::Error>,
+}
+
+struct Signal {
+    name: String,
+    identifier: String,
+    level: PinState,
+}
+
+impl<W: io::Write + Send + 'static> Recorder<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                writer: Some(writer),
+                signals: Vec::new(),
+                header_written: false,
+                last_timestamp: None,
+                error: None,
+            })),
+            listeners: Vec::new(),
+        }
+    }
+
+    /// Record every change of `pin` under the signal `name`
+    ///
+    /// All signals have to be added before the first change is recorded since the
+    /// file header declares every signal up front.
+    pub fn add(&mut self, name: &str, pin: &impl Observe) {
+        let index = {
+            let mut inner = self.inner.lock();
+            assert!(
+                !inner.header_written,
+                "signal {name:?} added after recording started"
+            );
+            let index = inner.signals.len();
+            inner.signals.push(Signal {
+                name: name.split_whitespace().collect::<Vec<_>>().join("_"),
+                identifier: identifier(index),
+                level: pin.level(),
+            });
+            index
+        };
+
+        // The listener is removed when the recorder is dropped, until then it must
+        // not keep the recorder alive
+        let inner = Arc::downgrade(&self.inner);
+        let listener = pin.on_change(move |level| {
+            if let Some(inner) = inner.upgrade() {
+                inner.lock().record(index, level);
+            }
+        });
+        self.listeners.push(listener);
+    }
+
+    /// Write out everything recorded so far
+    ///
+    /// This also reports any error that happened while recording.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let mut inner = self.inner.lock();
+        inner.write_header();
+        if let Some(error) = inner.error.take() {
+            return Err(error);
+        }
+        match &mut inner.writer {
+            Some(writer) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Stop recording and return the writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.flush()?;
+        Ok(self.inner.lock().writer.take().unwrap())
+    }
+}
+
+impl<W: io::Write + Send + 'static> Drop for Recorder<W> {
+    fn drop(&mut self) {
+        self.listeners.drain(..).for_each(Listener::remove);
+        if let Err(e) = self.flush() {
+            log::error!("Failed to write vcd: {e}");
+        }
+    }
+}
+
+impl<W: io::Write> Inner<W> {
+    fn write_header(&mut self) {
+        if self.header_written {
+            return;
+        }
+        self.header_written = true;
+
+        let mut header = String::from("$timescale 1ns $end\n$scope module gpio $end\n");
+        for signal in &self.signals {
+            header += &format!("$var wire 1 {} {} $end\n", signal.identifier, signal.name);
+        }
+        header += "$upscope $end\n$enddefinitions $end\n#0\n$dumpvars\n";
+        for signal in &self.signals {
+            header += &format!("{}{}\n", bit(signal.level), signal.identifier);
+        }
+        header += "$end\n";
+        self.write(header.as_bytes());
+    }
+
+    fn record(&mut self, index: usize, level: PinState) {
+        self.write_header();
+        self.signals[index].level = level;
+
+        let mut change = String::new();
+        // Pins changed from threads with different clocks may go back in time, which a
+        // value change dump can not represent
+        let timestamp = time::now().since_start().as_nanos();
+        let timestamp = timestamp.max(self.last_timestamp.unwrap_or(0));
+        if self.last_timestamp != Some(timestamp) {
+            self.last_timestamp = Some(timestamp);
+            change += &format!("#{timestamp}\n");
+        }
+        change += &format!("{}{}\n", bit(level), self.signals[index].identifier);
+        self.write(change.as_bytes());
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Some(writer) = &mut self.writer
+            && let Err(e) = writer.write_all(bytes)
+        {
+            self.error = Some(e);
+        }
+    }
+}
+
+fn bit(level: PinState) -> char {
+    match level {
+        PinState::Low => '0',
+        PinState::High => '1',
+    }
+}
+
+/// Short identifier code made of the printable ascii characters
+fn identifier(mut index: usize) -> String {
+    const FIRST: u8 = b'!';
+    const COUNT: usize = (b'~' - FIRST + 1) as usize;
+
+    let mut identifier = String::new();
+    loop {
+        identifier.push((FIRST + (index % COUNT) as u8) as char);
+        index /= COUNT;
+        if index == 0 {
+            return identifier;
+        }
+        index -= 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Recorder;
+    use crate::{gpio, time::VirtualClock};
+    use embedded_hal::digital::{OutputPin, PinState};
+    use parking_lot::Mutex;
+    use std::{io, sync::Arc, time::Duration};
+
+    #[test]
+    fn test() {
+        let clock = VirtualClock::new();
+        let (_led_stimulus, mut led) = gpio::new(PinState::Low);
+        let (button, mut button_stimulus) = gpio::new(PinState::High);
+
+        let mut recorder = Recorder::new(Vec::new());
+        recorder.add("status led", &led);
+        recorder.add("button", &button);
+
+        clock.block_on(async {
+            crate::sleep(Duration::from_micros(1)).await;
+            led.set_high().unwrap();
+            button_stimulus.set_low().unwrap();
+            led.set_high().unwrap();
+            crate::sleep(Duration::from_micros(1)).await;
+            led.set_low().unwrap();
+        });
+
+        let vcd = String::from_utf8(recorder.finish().unwrap()).unwrap();
+        assert_eq!(
+            vcd,
+            "$timescale 1ns $end\n\
+             $scope module gpio $end\n\
+             $var wire 1 ! status_led $end\n\
+             $var wire 1 \" button $end\n\
+             $upscope $end\n\
+             $enddefinitions $end\n\
+             #0\n\
+             $dumpvars\n\
+             0!\n\
+             1\"\n\
+             $end\n\
+             #1000\n\
+             1!\n\
+             0\"\n\
+             #2000\n\
+             0!\n"
+        );
+    }
+
+    #[test]
+    fn drop() {
+        #[derive(Clone, Default)]
+        struct Shared(Arc<Mutex<Vec<u8>>>);
+
+        impl io::Write for Shared {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.lock().write(buf)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let (_led_stimulus, mut led) = gpio::new(PinState::Low);
+        let output = Shared::default();
+        let mut recorder = Recorder::new(output.clone());
+        recorder.add("led", &led);
+        std::mem::drop(recorder);
+
+        let len = output.0.lock().len();
+        led.set_high().unwrap();
+        assert_eq!(output.0.lock().len(), len);
+    }
+}